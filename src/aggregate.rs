@@ -0,0 +1,402 @@
+//! Aggregation of hourly [`Report`]s into daily, weekly, monthly or custom periods
+//!
+//! Periods follow the local calendar of each segment, from [`Report::timezone`], so a day is midnight to midnight local
+//! time, also on the days daylight saving time starts or ends. The totals of an [`Aggregate`] are a [`Report`], so they
+//! can be exported like the reports from the API, with the `date` at the start of the period and the `interval` of the
+//! reports that were summed, as the API has no weekly or monthly interval. The end of the period is kept next to it.
+//!
+//! - counts, including the left and right counts, are summed
+//! - the uptime is the mean of the uptimes, weighted by the length of each report's interval
+//! - the speed histograms and `v85` are merged like a [`SpeedDistribution`], weighted by the number of cars
+
+use std::{collections::BTreeMap, time::SystemTime};
+
+use chrono::{DateTime, Datelike, Months, NaiveDate};
+use chrono_tz::Tz;
+#[cfg(feature = "clap")]
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    export::{self, Tabular},
+    response::Report,
+    speed::{SpeedDistribution, SpeedHistogram},
+    time_range,
+};
+
+/// A calendar period to aggregate reports into, in local time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum Period {
+    /// From midnight to midnight
+    Daily,
+    /// ISO weeks, from Monday to Sunday
+    Weekly,
+    /// Calendar months
+    Monthly,
+}
+
+impl Period {
+    /// The first day of the period that contains the date
+    pub fn first_day(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Daily => date,
+            Self::Weekly => date - chrono::Days::new(date.weekday().num_days_from_monday().into()),
+            Self::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    /// The first day of the next period, after the period that starts on the first day
+    pub fn next(self, first_day: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Daily => first_day + chrono::Days::new(1),
+            Self::Weekly => first_day + chrono::Days::new(7),
+            Self::Monthly => first_day + Months::new(1),
+        }
+    }
+}
+
+/// The totals of the reports of a segment (or instance) in a period
+#[derive(Clone, Debug, Serialize)]
+pub struct Aggregate {
+    /// The totals, with the `date` at the start of the period and the `interval` of the reports that were summed
+    #[serde(flatten)]
+    pub report: Report,
+    /// The end of the period, not included
+    #[serde(with = "humantime_serde")]
+    pub end: SystemTime,
+}
+
+/// Aggregate the reports per segment (and instance) and period, ordered by segment and then time
+///
+/// Each aggregate runs from the start of its period to the start of the next one, in local time.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a report has an unknown time zone
+pub fn aggregate(reports: &[Report], period: Period) -> Result<Vec<Aggregate>, Error> {
+    aggregate_by(reports, |local| {
+        let first_day = period.first_day(local.date_naive());
+        (
+            time_range::start_of_day(first_day, &local.timezone()),
+            time_range::start_of_day(period.next(first_day), &local.timezone()),
+        )
+    })
+}
+
+/// Aggregate the reports per segment (and instance) into custom buckets, ordered by segment and then time
+///
+/// `bucket` maps the local start of a report to the start and end of its bucket, e.g. to roll hourly reports into the
+/// morning and evening of each day.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a report has an unknown time zone
+pub fn aggregate_by<F>(reports: &[Report], mut bucket: F) -> Result<Vec<Aggregate>, Error>
+where
+    F: FnMut(&DateTime<Tz>) -> (SystemTime, SystemTime),
+{
+    let mut buckets = BTreeMap::<_, (SystemTime, Vec<&Report>)>::new();
+    for report in reports {
+        let (start, end) = bucket(&report.local_start()?);
+        buckets
+            .entry((report.segment_id, report.instance_id, start))
+            .or_insert_with(|| (end, Vec::new()))
+            .1
+            .push(report);
+    }
+
+    Ok(buckets
+        .into_iter()
+        .filter_map(|((_, _, start), (end, reports))| {
+            Some(Aggregate {
+                report: totals(start, &reports)?,
+                end,
+            })
+        })
+        .collect())
+}
+
+impl Tabular for Aggregate {
+    fn columns() -> Vec<String> {
+        let mut columns = export::report_columns();
+        columns.push("end".to_string());
+        columns
+    }
+
+    fn record(&self) -> Vec<String> {
+        let mut record = export::report_record(&self.report);
+        record.push(humantime::format_rfc3339(self.end).to_string());
+        record
+    }
+}
+
+/// The totals of the reports in a bucket, as a report dated at the start of the bucket, `None` if there are no reports
+fn totals(start: SystemTime, reports: &[&Report]) -> Option<Report> {
+    let first = *reports.first()?;
+
+    let mut sums = [0.0; 12];
+    let (mut uptime, mut hours) = (0.0, 0.0);
+    for report in reports {
+        for (sum, count) in sums.iter_mut().zip(counts(report)) {
+            *sum += f64::from(count);
+        }
+
+        let duration = report.interval.duration().as_secs_f64() / 3600.0;
+        uptime += f64::from(report.uptime) * duration;
+        hours += duration;
+    }
+    let [heavy, car, bike, pedestrian, heavy_lft, heavy_rgt, car_lft, car_rgt, bike_lft, bike_rgt, pedestrian_lft, pedestrian_rgt] =
+        sums.map(|sum| sum as f32);
+
+    let coarse = SpeedDistribution::from_reports(reports.iter().copied(), SpeedHistogram::Coarse);
+    let fine = SpeedDistribution::from_reports(reports.iter().copied(), SpeedHistogram::Fine);
+
+    Some(Report {
+        date: start,
+        uptime: if hours > 0.0 {
+            (uptime / hours) as f32
+        } else {
+            0.0
+        },
+        heavy,
+        car,
+        bike,
+        pedestrian,
+        heavy_lft,
+        heavy_rgt,
+        car_lft,
+        car_rgt,
+        bike_lft,
+        bike_rgt,
+        pedestrian_lft,
+        pedestrian_rgt,
+        v85: coarse
+            .as_ref()
+            .and_then(|speeds| speeds.server_v85)
+            .unwrap_or_default() as f32,
+        car_speed_hist_0to70plus: percentages(coarse),
+        car_speed_hist_0to120plus: percentages(fine),
+        ..first.clone()
+    })
+}
+
+/// The histogram of the distribution in percent of the cars, as in the reports, empty without a distribution
+fn percentages(speeds: Option<SpeedDistribution>) -> Vec<f32> {
+    speeds
+        .map(|speeds| {
+            speeds
+                .shares
+                .iter()
+                .map(|share| (share * 100.0) as f32)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The counts of a report, in the order of the fields of [`Report`]
+fn counts(report: &Report) -> [f32; 12] {
+    [
+        report.heavy,
+        report.car,
+        report.bike,
+        report.pedestrian,
+        report.heavy_lft,
+        report.heavy_rgt,
+        report.car_lft,
+        report.car_rgt,
+        report.bike_lft,
+        report.bike_rgt,
+        report.pedestrian_lft,
+        report.pedestrian_rgt,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Days, Weekday};
+
+    use crate::response::ReportInterval;
+
+    use super::*;
+
+    fn report(date: &str, uptime: f32, car: f32, hist: [f32; 8], v85: f32) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": {uptime}, "heavy": 1, "car": {car}, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": {car}, "car_rgt": 0, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": {hist:?}, "car_speed_hist_0to120plus": [], "v85": {v85}}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    fn time(time: &str) -> SystemTime {
+        humantime::parse_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn test_daily() {
+        let reports = [
+            // 23:00 local on the 25th
+            report(
+                "2023-03-25T22:00:00Z",
+                1.0,
+                10.0,
+                [0.0, 0.0, 100.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                30.0,
+            ),
+            // the 26th has only 23 hours, it starts at 23:00 UTC in winter time and ends at 22:00 UTC in summer time
+            report(
+                "2023-03-25T23:00:00Z",
+                0.5,
+                10.0,
+                [0.0, 0.0, 100.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                30.0,
+            ),
+            report(
+                "2023-03-26T21:00:00Z",
+                1.0,
+                30.0,
+                [0.0, 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 0.0],
+                50.0,
+            ),
+            report("2023-03-26T22:00:00Z", 1.0, 10.0, [0.0; 8], 0.0),
+        ];
+
+        let days = aggregate(&reports, Period::Daily).unwrap();
+        assert_eq!(3, days.len());
+        assert_eq!(time("2023-03-24T23:00:00Z"), days[0].report.date);
+        assert_eq!(time("2023-03-25T23:00:00Z"), days[1].report.date);
+        assert_eq!(time("2023-03-26T22:00:00Z"), days[1].end);
+        assert_eq!(time("2023-03-26T22:00:00Z"), days[2].report.date);
+
+        let day = &days[1].report;
+        assert_eq!(ReportInterval::Hourly, day.interval);
+        assert_eq!(40.0, day.car);
+        assert_eq!(40.0, day.car_lft);
+        assert_eq!(2.0, day.heavy);
+        assert_eq!(0.75, day.uptime);
+        assert_eq!(45.0, day.v85);
+        assert_eq!(
+            vec![0.0, 0.0, 25.0, 0.0, 75.0, 0.0, 0.0, 0.0],
+            day.car_speed_hist_0to70plus
+        );
+        assert!(day.car_speed_hist_0to120plus.is_empty());
+    }
+
+    #[test]
+    fn test_weekly_and_monthly() {
+        let hist = [0.0, 0.0, 100.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let reports = [
+            // Sunday 2024-03-31, local time
+            report("2024-03-31T10:00:00Z", 1.0, 10.0, hist, 30.0),
+            // Monday 2024-04-01, local time
+            report("2024-04-01T10:00:00Z", 1.0, 20.0, hist, 30.0),
+            report("2024-04-02T10:00:00Z", 1.0, 30.0, hist, 30.0),
+        ];
+
+        let weeks = aggregate(&reports, Period::Weekly).unwrap();
+        assert_eq!(2, weeks.len());
+        assert_eq!(time("2024-03-24T23:00:00Z"), weeks[0].report.date);
+        assert_eq!(time("2024-03-31T22:00:00Z"), weeks[0].end);
+        assert_eq!(time("2024-03-31T22:00:00Z"), weeks[1].report.date);
+        assert_eq!(time("2024-04-07T22:00:00Z"), weeks[1].end);
+        assert_eq!(50.0, weeks[1].report.car);
+
+        let months = aggregate(&reports, Period::Monthly).unwrap();
+        assert_eq!(2, months.len());
+        assert_eq!(time("2024-02-29T23:00:00Z"), months[0].report.date);
+        assert_eq!(time("2024-03-31T22:00:00Z"), months[1].report.date);
+        // April has 30 days
+        assert_eq!(time("2024-04-30T22:00:00Z"), months[1].end);
+
+        let mut csv = Vec::new();
+        export::write_csv(&mut csv, &months).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.lines().next().unwrap().ends_with(",end"));
+        assert!(csv
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with(",2024-04-30T22:00:00Z"));
+    }
+
+    #[test]
+    fn test_histograms_like_speed_distribution() {
+        let reports = [
+            // the percentages add up to 50, they are scaled up
+            report(
+                "2024-04-01T05:00:00Z",
+                1.0,
+                30.0,
+                [0.0, 0.0, 25.0, 25.0, 0.0, 0.0, 0.0, 0.0],
+                30.0,
+            ),
+            report(
+                "2024-04-01T06:00:00Z",
+                1.0,
+                10.0,
+                [0.0, 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 0.0],
+                48.0,
+            ),
+        ];
+
+        let days = aggregate(&reports, Period::Daily).unwrap();
+        let speeds = SpeedDistribution::from_reports(&reports, SpeedHistogram::Coarse).unwrap();
+        assert_eq!(
+            vec![0.0, 0.0, 37.5, 37.5, 25.0, 0.0, 0.0, 0.0],
+            days[0].report.car_speed_hist_0to70plus
+        );
+        assert_eq!(
+            speeds
+                .shares
+                .iter()
+                .map(|share| (share * 100.0) as f32)
+                .collect::<Vec<_>>(),
+            days[0].report.car_speed_hist_0to70plus
+        );
+        assert_eq!(Some(f64::from(days[0].report.v85)), speeds.server_v85);
+    }
+
+    #[test]
+    fn test_custom() {
+        let hist = [0.0; 8];
+        let reports = [
+            // Friday 23:00 local time
+            report("2024-03-29T22:00:00Z", 1.0, 10.0, hist, 0.0),
+            // Saturday 00:00 and Sunday 23:00, summer time starts on Sunday
+            report("2024-03-29T23:00:00Z", 1.0, 20.0, hist, 0.0),
+            report("2024-03-31T21:00:00Z", 1.0, 30.0, hist, 0.0),
+            // Monday 00:00
+            report("2024-03-31T22:00:00Z", 1.0, 40.0, hist, 0.0),
+        ];
+
+        // the weekend as one bucket, the other days on their own
+        let buckets = aggregate_by(&reports, |local| {
+            let date = local.date_naive();
+            let first_day = if date.weekday() == Weekday::Sun {
+                date - Days::new(1)
+            } else {
+                date
+            };
+            let days = if first_day.weekday() == Weekday::Sat {
+                2
+            } else {
+                1
+            };
+            (
+                time_range::start_of_day(first_day, &local.timezone()),
+                time_range::start_of_day(first_day + Days::new(days), &local.timezone()),
+            )
+        })
+        .unwrap();
+
+        assert_eq!(3, buckets.len());
+        let weekend = &buckets[1];
+        assert_eq!(time("2024-03-29T23:00:00Z"), weekend.report.date);
+        // 47 hours, with the hour skipped on Sunday
+        assert_eq!(time("2024-03-31T22:00:00Z"), weekend.end);
+        assert_eq!(50.0, weekend.report.car);
+        assert_eq!(buckets[0].end, weekend.report.date);
+        assert_eq!(weekend.end, buckets[2].report.date);
+        assert_eq!(0.0, weekend.report.v85);
+    }
+}
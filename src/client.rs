@@ -1,20 +1,196 @@
 //! Client library, based on reqwest, this sets up connection with required parameters for the Telraam API endpoints
 
-use std::error::Error;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use reqwest::{
     blocking::Client,
-    header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE},
+    header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, ACCEPT, CONTENT_TYPE},
+    StatusCode,
 };
+use serde::de::DeserializeOwned;
+
+pub use reqwest::header;
 
-use crate::endpoint::Endpoint;
+use crate::{
+    endpoint::{Endpoint, Traffic, TrafficRequest, MAX_TRAFFIC_INTERVAL},
+    error::{self, Error},
+    rate_limit::RateLimiter,
+    response::{self, MultiTraffic, Report},
+    retry::{self, Attempt, Attempted, RetryPolicy},
+};
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
-const TELRAAM_NET: &str = "https://telraam-api.net";
+/// The default base URL of the Telraam API
+pub const TELRAAM_NET: &str = "https://telraam-api.net";
+
+/// Builder for [`TelraamClient`] (and `AsyncTelraamClient` with the `async` feature), for anything other than the defaults
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use telraam::client::TelraamClientBuilder;
+///
+/// let client = TelraamClientBuilder::new("my-token")
+///     .base_url("http://localhost:8080")
+///     .timeout(Duration::from_secs(30))
+///     .build()
+///     .expect("failed to build client");
+/// ```
+#[derive(Clone, Debug)]
+pub struct TelraamClientBuilder {
+    api_token: String,
+    base_url: String,
+    version: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    headers: HeaderMap,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl TelraamClientBuilder {
+    /// Constructs a new builder with the defaults, i.e. [`TELRAAM_NET`] and [`crate::VER`]
+    ///
+    /// # Arguments
+    ///
+    /// * `api_token` - The API token from [Telraam](https://telraam.net/en/admin/mijn-eigen-telraam/tokens) for this connection.
+    pub fn new(api_token: &str) -> Self {
+        Self {
+            api_token: api_token.to_string(),
+            base_url: TELRAAM_NET.to_string(),
+            version: crate::VER.to_string(),
+            timeout: None,
+            connect_timeout: None,
+            headers: HeaderMap::new(),
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+        }
+    }
+
+    /// The scheme and host (and optional path prefix) of the API, e.g. a local mock server, or a proxy
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// The API version prefix added to all endpoint paths, e.g. `v1`
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Total timeout for each request, from connecting until the response body has been read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for establishing the connection
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// An additional header to send with every request, this will replace any existing value for the header
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// The policy for retrying failed requests, used by `send`, defaults to [`RetryPolicy::default`]
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limit the requests made by the client, every attempt (including retries) counts towards the limit
+    ///
+    /// The limiter can be shared with other clients using the same API token, by cloning it.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// The root URL for all endpoints, e.g. `https://telraam-api.net/v1`
+    fn root_url(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        let version = self.version.trim_matches('/');
+
+        if version.is_empty() {
+            base.to_string()
+        } else {
+            format!("{base}/{version}")
+        }
+    }
+
+    fn headers(&self) -> Result<HeaderMap, InvalidHeaderValue> {
+        let mut headers = default_headers(&self.api_token)?;
+        headers.extend(self.headers.clone());
+        Ok(headers)
+    }
+
+    /// Build the blocking [`TelraamClient`]
+    pub fn build(self) -> Result<TelraamClient, Error> {
+        let mut builder = reqwest::blocking::ClientBuilder::new()
+            .user_agent(APP_USER_AGENT)
+            .default_headers(self.headers()?);
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+
+        Ok(TelraamClient {
+            client: builder.build().map_err(Error::Client)?,
+            root_url: self.root_url(),
+            retry_policy: self.retry_policy,
+            rate_limiter: self.rate_limiter,
+        })
+    }
+
+    /// Build the [`AsyncTelraamClient`]
+    #[cfg(feature = "async")]
+    pub fn build_async(self) -> Result<AsyncTelraamClient, Error> {
+        let mut builder = reqwest::ClientBuilder::new()
+            .user_agent(APP_USER_AGENT)
+            .default_headers(self.headers()?);
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+
+        Ok(AsyncTelraamClient {
+            client: builder.build().map_err(Error::Client)?,
+            root_url: self.root_url(),
+            retry_policy: self.retry_policy,
+            rate_limiter: self.rate_limiter,
+        })
+    }
+}
 
 /// An HTTPS Client for working with the Telraam API
-pub struct TelraamClient(Client);
+///
+/// The client is cheap to clone, clones share the connection pool and the rate limiter.
+#[derive(Clone)]
+pub struct TelraamClient {
+    client: Client,
+    root_url: String,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+}
 
 impl TelraamClient {
     /// Constructs a new Client
@@ -22,25 +198,24 @@ impl TelraamClient {
     /// # Arguments
     ///
     /// * `new` - The API token from [Telraam](https://telraam.net/en/admin/mijn-eigen-telraam/tokens) for this connection.
-    pub fn new(api_token: &str) -> Result<Self, Box<dyn Error>> {
-        let mut headers = HeaderMap::new();
-        let mut api_token = HeaderValue::from_str(api_token)?;
-        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-
-        api_token.set_sensitive(true);
-        headers.insert("X-Api-Key", api_token);
+    pub fn new(api_token: &str) -> Result<Self, Error> {
+        TelraamClientBuilder::new(api_token).build()
+    }
 
-        let client = reqwest::blocking::ClientBuilder::new()
-            .user_agent(APP_USER_AGENT)
-            .default_headers(headers)
-            .build()?;
+    /// Returns a builder to configure the base URL, API version, timeouts and headers of the Client
+    pub fn builder(api_token: &str) -> TelraamClientBuilder {
+        TelraamClientBuilder::new(api_token)
+    }
 
-        Ok(Self(client))
+    /// The policy used for retrying failed requests in [`Self::send`]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
     }
 
     /// Send a request to the given endpoint, the response is endpoint specific
     ///
+    /// Failed requests are retried according to the client's [`RetryPolicy`].
+    ///
     /// # Argument
     ///
     /// * `endpoint` - The endpoint to use for the connection
@@ -48,29 +223,676 @@ impl TelraamClient {
     /// # Returns
     ///
     /// The result is endpoint specific, but will always be serializable, see `serde_json::to_string_pretty`
-    pub fn send<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Box<dyn Error>> {
-        let mut url = format!(
-            "{base}/{version}/{endpoint}",
-            base = TELRAAM_NET,
-            version = crate::VER,
-            endpoint = E::PATH
-        );
+    ///
+    /// # Errors
+    ///
+    /// See [`Error`], the Telraam `status_code` in the body is not checked here, but in the accessors of the response
+    pub fn send<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        self.send_with(endpoint, &self.retry_policy)
+    }
+
+    /// Send a request to the given endpoint, retrying with the given policy instead of the client's
+    pub fn send_with<E: Endpoint>(
+        &self,
+        endpoint: &E,
+        retry_policy: &RetryPolicy,
+    ) -> Result<E::Response, Error> {
+        self.send_with_attempts(endpoint, retry_policy).result
+    }
+
+    /// Send a request to the given endpoint, retrying with the given policy, and return a record of all attempts made
+    pub fn send_with_attempts<E: Endpoint>(
+        &self,
+        endpoint: &E,
+        retry_policy: &RetryPolicy,
+    ) -> Attempted<E::Response> {
+        let mut attempts = Vec::new();
+        let mut number = 0;
+
+        loop {
+            number += 1;
+            let start = Instant::now();
+            let result = self.send_once(endpoint);
+            let delay = result
+                .as_ref()
+                .err()
+                .and_then(|error| retry_policy.retry_delay(number, error, E::IDEMPOTENT));
+
+            attempts.push(Attempt::new(number, start.elapsed(), &result, delay));
+            match delay {
+                Some(delay) => thread::sleep(delay),
+                None => return Attempted { result, attempts },
+            }
+        }
+    }
+
+    /// Fetch the traffic reports for the request, the interval can be longer than the API allows in a single call
+    ///
+    /// Long intervals are split into [`MAX_TRAFFIC_INTERVAL`] windows (see [`TrafficRequest::windows`]), which are
+    /// requested in order, the reports are then merged with [`response::merge_reports`].
+    pub fn traffic(&self, request: &TrafficRequest) -> Result<Vec<Report>, Error> {
+        let mut reports = Vec::new();
+        for window in request.windows(MAX_TRAFFIC_INTERVAL) {
+            reports.extend(self.send(&Traffic::new(window))?.take_reports()?);
+        }
+
+        Ok(response::merge_reports(reports))
+    }
+
+    /// Fetch the traffic reports of several segments (or instances), with `concurrency` requests in flight at once
+    ///
+    /// The `request` is sent for each of the `ids`, replacing its `id`. Requests still go through the client's
+    /// [`RateLimiter`], which is shared between the concurrent requests. A failure for one identifier is recorded in
+    /// the result, and does not stop the others.
+    pub fn traffic_many<I>(
+        &self,
+        request: &TrafficRequest,
+        ids: I,
+        concurrency: usize,
+    ) -> MultiTraffic
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let ids = ids.into_iter().map(Into::into).collect::<Vec<String>>();
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(MultiTraffic::default());
+
+        thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, ids.len().max(1)) {
+                scope.spawn(|| {
+                    while let Some(id) = ids.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let request = TrafficRequest {
+                            id: id.clone(),
+                            ..request.clone()
+                        };
+                        let result = self.traffic(&request);
+
+                        results
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .insert(id.clone(), result);
+                    }
+                });
+            }
+        });
+
+        results.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The rate limiter of the client, if any
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    fn send_once<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            thread::sleep(rate_limiter.acquire()?);
+        }
+
+        let path = endpoint_path(endpoint);
+        let request = self
+            .client
+            .request(E::METHOD, format!("{}/{path}", self.root_url))
+            .query(&endpoint.params());
 
-        // add the path params, for things like instance IDs
-        if let Some(path_params) = endpoint.path_params() {
-            url.push('/');
-            url.push_str(path_params)
+        let request = if let Some(payload) = endpoint.payload() {
+            request.body(encode(&path, payload)?)
+        } else {
+            request
         };
 
-        let request = self.0.request(E::METHOD, url).query(&endpoint.params());
+        let transport = |source| Error::Transport {
+            path: path.clone(),
+            source,
+        };
+        let response = request.send().map_err(transport)?;
+        let status = response.status();
+        let retry_after = retry::parse_retry_after(response.headers());
+        let body = response.text().map_err(transport)?;
+
+        decode(&path, status, retry_after, &body)
+    }
+}
+
+/// An async HTTPS Client for working with the Telraam API, for use from within an async runtime like tokio
+///
+/// This accepts the same [`Endpoint`]s and returns the same [`crate::response::Response`] types as [`TelraamClient`].
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct AsyncTelraamClient {
+    client: reqwest::Client,
+    root_url: String,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncTelraamClient {
+    /// Constructs a new async Client
+    ///
+    /// # Arguments
+    ///
+    /// * `new` - The API token from [Telraam](https://telraam.net/en/admin/mijn-eigen-telraam/tokens) for this connection.
+    pub fn new(api_token: &str) -> Result<Self, Error> {
+        TelraamClientBuilder::new(api_token).build_async()
+    }
+
+    /// Returns a builder to configure the base URL, API version, timeouts and headers of the Client
+    pub fn builder(api_token: &str) -> TelraamClientBuilder {
+        TelraamClientBuilder::new(api_token)
+    }
+
+    /// The policy used for retrying failed requests in [`Self::send`]
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Send a request to the given endpoint, the response is endpoint specific, see [`TelraamClient::send`]
+    ///
+    /// The client is cheap to clone, so many requests (e.g. over different segments) can be sent in parallel.
+    pub async fn send<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        self.send_with(endpoint, &self.retry_policy).await
+    }
+
+    /// Send a request to the given endpoint, retrying with the given policy instead of the client's
+    pub async fn send_with<E: Endpoint>(
+        &self,
+        endpoint: &E,
+        retry_policy: &RetryPolicy,
+    ) -> Result<E::Response, Error> {
+        self.send_with_attempts(endpoint, retry_policy).await.result
+    }
+
+    /// Send a request to the given endpoint, retrying with the given policy, and return a record of all attempts made
+    pub async fn send_with_attempts<E: Endpoint>(
+        &self,
+        endpoint: &E,
+        retry_policy: &RetryPolicy,
+    ) -> Attempted<E::Response> {
+        let mut attempts = Vec::new();
+        let mut number = 0;
+
+        loop {
+            number += 1;
+            let start = Instant::now();
+            let result = self.send_once(endpoint).await;
+            let delay = result
+                .as_ref()
+                .err()
+                .and_then(|error| retry_policy.retry_delay(number, error, E::IDEMPOTENT));
+
+            attempts.push(Attempt::new(number, start.elapsed(), &result, delay));
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Attempted { result, attempts },
+            }
+        }
+    }
+
+    /// Fetch the traffic reports for the request, the interval can be longer than the API allows, see [`TelraamClient::traffic`]
+    pub async fn traffic(&self, request: &TrafficRequest) -> Result<Vec<Report>, Error> {
+        let mut reports = Vec::new();
+        for window in request.windows(MAX_TRAFFIC_INTERVAL) {
+            reports.extend(self.send(&Traffic::new(window)).await?.take_reports()?);
+        }
+
+        Ok(response::merge_reports(reports))
+    }
+
+    /// Fetch the traffic reports of several segments (or instances), with `concurrency` requests in flight at once, see [`TelraamClient::traffic_many`]
+    pub async fn traffic_many<I>(
+        &self,
+        request: &TrafficRequest,
+        ids: I,
+        concurrency: usize,
+    ) -> MultiTraffic
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        use futures_util::StreamExt;
+
+        let requests = ids.into_iter().map(|id| TrafficRequest {
+            id: id.into(),
+            ..request.clone()
+        });
+
+        futures_util::stream::iter(requests)
+            .map(|request| async move {
+                let result = self.traffic(&request).await;
+                (request.id, result)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .fold(
+                MultiTraffic::default(),
+                |mut results, (id, result)| async move {
+                    results.insert(id, result);
+                    results
+                },
+            )
+            .await
+    }
+
+    /// The rate limiter of the client, if any
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    async fn send_once<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            tokio::time::sleep(rate_limiter.acquire()?).await;
+        }
+
+        let path = endpoint_path(endpoint);
+        let request = self
+            .client
+            .request(E::METHOD, format!("{}/{path}", self.root_url))
+            .query(&endpoint.params());
 
         let request = if let Some(payload) = endpoint.payload() {
-            let body = serde_json::to_string(&payload)?;
-            request.body(body)
+            request.body(encode(&path, payload)?)
         } else {
             request
         };
 
-        Ok(request.send()?.json()?)
+        let transport = |source| Error::Transport {
+            path: path.clone(),
+            source,
+        };
+        let response = request.send().await.map_err(transport)?;
+        let status = response.status();
+        let retry_after = retry::parse_retry_after(response.headers());
+        let body = response.text().await.map_err(transport)?;
+
+        decode(&path, status, retry_after, &body)
+    }
+}
+
+/// The headers sent with every request, including the API token
+fn default_headers(api_token: &str) -> Result<HeaderMap, InvalidHeaderValue> {
+    let mut headers = HeaderMap::new();
+    let mut api_token = HeaderValue::from_str(api_token)?;
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    api_token.set_sensitive(true);
+    headers.insert("X-Api-Key", api_token);
+
+    Ok(headers)
+}
+
+/// The path of the endpoint, relative to the root URL, e.g. `cameras/segment/348917`
+fn endpoint_path<E: Endpoint>(endpoint: &E) -> String {
+    let mut path = E::PATH.to_string();
+
+    // add the path params, for things like instance IDs
+    if let Some(path_params) = endpoint.path_params() {
+        path.push('/');
+        path.push_str(path_params)
+    };
+
+    path
+}
+
+fn encode<T: serde::Serialize>(path: &str, payload: &T) -> Result<String, Error> {
+    serde_json::to_string(payload).map_err(|source| Error::Encode {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// Check the HTTP status and decode the body into the response
+fn decode<R: DeserializeOwned>(
+    path: &str,
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: &str,
+) -> Result<R, Error> {
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(Error::Unauthorized {
+            path: path.to_string(),
+            status,
+            body: error::snippet(body),
+        });
+    }
+
+    if !status.is_success() {
+        return Err(Error::HttpStatus {
+            path: path.to_string(),
+            status,
+            body: error::snippet(body),
+            retry_after,
+        });
+    }
+
+    serde_json::from_str(body).map_err(|source| Error::Decode {
+        path: path.to_string(),
+        status,
+        snippet: error::snippet(body),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        endpoint::{CamerasBySegmentId, Welcome},
+        rate_limit::RateLimit,
+        response::Response,
+        test_util::{MockResponse, MockServer},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_root_url() {
+        let builder = TelraamClientBuilder::new("token");
+        assert_eq!("https://telraam-api.net/v1", builder.root_url());
+
+        let builder = builder.base_url("http://localhost:8080/").version("/v2/");
+        assert_eq!("http://localhost:8080/v2", builder.root_url());
+
+        let builder = builder.version("");
+        assert_eq!("http://localhost:8080", builder.root_url());
+    }
+
+    #[test]
+    fn test_invalid_token() {
+        let error = TelraamClient::new("bad\ntoken").err().expect("should fail");
+        assert!(matches!(error, Error::InvalidToken(_)));
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn test_send_errors() {
+        let server = MockServer::start(vec![
+            MockResponse::status(503, "try again later"),
+            MockResponse::status(403, r#"{"message": "Forbidden"}"#),
+            MockResponse::ok(r#"{"status_code": 200, "message": "ok", "cameras": [{"#),
+        ]);
+
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .expect("failed to build client");
+        let endpoint = CamerasBySegmentId {
+            segment_id: "348917".to_string(),
+        };
+
+        let error = client.send(&endpoint).err().expect("should fail");
+        assert!(matches!(
+            &error,
+            Error::HttpStatus { status: StatusCode::SERVICE_UNAVAILABLE, body, .. } if body == "try again later"
+        ));
+        assert_eq!(Some("cameras/segment/348917"), error.path());
+        assert!(error.is_retryable());
+
+        let error = client.send(&endpoint).err().expect("should fail");
+        assert!(matches!(error, Error::Unauthorized { .. }));
+        assert_eq!(Some(StatusCode::FORBIDDEN), error.status());
+        assert!(!error.is_retryable());
+
+        let error = client.send(&endpoint).err().expect("should fail");
+        assert!(matches!(
+            &error,
+            Error::Decode { status: StatusCode::OK, snippet, .. } if snippet.ends_with(r#""cameras": [{"#)
+        ));
+        assert!(!error.is_retryable());
+
+        server.finish();
+    }
+
+    #[test]
+    fn test_send_with_retries() {
+        let server = MockServer::start(vec![
+            MockResponse::status(503, "unavailable"),
+            MockResponse::status(429, "slow down").with_header("Retry-After", "0"),
+            MockResponse::ok(r#"{"msg": "hello"}"#),
+        ]);
+
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .expect("failed to build client");
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+
+        let attempted = client.send_with_attempts(&Welcome, &policy);
+        assert_eq!(
+            "hello",
+            attempted.result.expect("should succeed").status().message
+        );
+        assert_eq!(3, attempted.attempts.len());
+        assert_eq!(
+            Some(StatusCode::SERVICE_UNAVAILABLE),
+            attempted.attempts[0].status
+        );
+        assert_eq!(Some(Duration::ZERO), attempted.attempts[1].delay);
+        assert!(attempted.attempts[2].is_success());
+        assert!(attempted.attempts[2].delay.is_none());
+
+        server.finish();
+    }
+
+    #[test]
+    fn test_send_quota_exceeded() {
+        let server = MockServer::start(vec![MockResponse::ok(r#"{"msg": "hello"}"#)]);
+
+        let rate_limiter = RateLimiter::new(RateLimit {
+            daily_quota: Some(1),
+            ..RateLimit::default()
+        });
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .rate_limiter(rate_limiter.clone())
+            .build()
+            .expect("failed to build client");
+
+        client.clone().send(&Welcome).expect("should succeed");
+        let error = client.send(&Welcome).err().expect("should fail");
+        assert!(matches!(error, Error::QuotaExceeded { quota: 1, .. }));
+        assert_eq!(1, rate_limiter.usage().requests);
+
+        assert_eq!(1, server.finish().len());
+    }
+
+    fn report_json(date: &str) -> String {
+        format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": 0.73, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5}}"#
+        )
+    }
+
+    #[test]
+    fn test_traffic_in_windows() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(format!(
+                r#"{{"status_code": 200, "message": "ok", "report": [{}, {}]}}"#,
+                report_json("2023-01-02T00:00:00.000Z"),
+                report_json("2023-01-01T00:00:00.000Z"),
+            )),
+            MockResponse::ok(format!(
+                r#"{{"status_code": 200, "message": "ok", "report": [{}, {}]}}"#,
+                report_json("2023-01-02T00:00:00.000Z"),
+                report_json("2023-06-01T00:00:00.000Z"),
+            )),
+            MockResponse::ok(r#"{"status_code": 200, "message": "ok", "report": []}"#),
+        ]);
+
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .build()
+            .expect("failed to build client");
+        let request = TrafficRequest {
+            level: crate::endpoint::TrafficLevel::Segments,
+            format: crate::endpoint::TrafficFormat::PerHour,
+            id: "348917".to_string(),
+            time_start: humantime::parse_rfc3339_weak("2023-01-01 00:00:00Z").unwrap(),
+            time_end: humantime::parse_rfc3339_weak("2023-07-01 00:00:00Z").unwrap(),
+        };
+
+        let reports = client.traffic(&request).expect("request failed");
+        let dates = reports
+            .iter()
+            .map(|report| humantime::format_rfc3339(report.date).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "2023-01-01T00:00:00Z",
+                "2023-01-02T00:00:00Z",
+                "2023-06-01T00:00:00Z"
+            ],
+            dates
+        );
+
+        let requests = server.finish();
+        assert_eq!(3, requests.len());
+        assert!(requests
+            .iter()
+            .all(|request| request.method == "POST" && request.path == "/v1/reports/traffic"));
+        assert!(requests[0]
+            .body
+            .contains(r#""time_start":"2023-01-01T00:00:00.000Z""#));
+        assert!(requests[2]
+            .body
+            .contains(r#""time_end":"2023-07-01T00:00:00.000Z""#));
+    }
+
+    #[test]
+    fn test_traffic_many() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(format!(
+                r#"{{"status_code": 200, "message": "ok", "report": [{}]}}"#,
+                report_json("2023-01-01T00:00:00.000Z"),
+            )),
+            MockResponse::status(404, r#"{"message": "segment not found"}"#),
+            MockResponse::ok(format!(
+                r#"{{"status_code": 200, "message": "ok", "report": [{}]}}"#,
+                report_json("2023-01-01T01:00:00.000Z"),
+            )),
+        ]);
+
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .expect("failed to build client");
+        let request = TrafficRequest {
+            level: crate::endpoint::TrafficLevel::Segments,
+            format: crate::endpoint::TrafficFormat::PerHour,
+            id: String::new(),
+            time_start: humantime::parse_rfc3339_weak("2023-01-01 00:00:00Z").unwrap(),
+            time_end: humantime::parse_rfc3339_weak("2023-01-02 00:00:00Z").unwrap(),
+        };
+
+        // the mock server answers in the order of the connections, so which id fails is not known
+        let results = client.traffic_many(&request, ["1", "2", "3"], 2);
+        assert_eq!(2, results.succeeded().count());
+        assert_eq!(1, results.failed().count());
+        assert!(!results.is_complete());
+        assert!(results
+            .failures
+            .values()
+            .all(|error| error.status() == Some(StatusCode::NOT_FOUND)));
+        assert_eq!(2, results.merged_reports().len());
+
+        let mut bodies = server
+            .finish()
+            .into_iter()
+            .map(|request| request.body)
+            .collect::<Vec<_>>();
+        bodies.sort();
+        assert!(bodies[0].contains(r#""id":"1""#));
+        assert!(bodies[2].contains(r#""id":"3""#));
+    }
+
+    #[test]
+    fn test_send_to_base_url() {
+        let server = MockServer::start(vec![MockResponse::ok(
+            r#"{"msg": "hello! Telraam server 2.0 is up and running"}"#,
+        )]);
+
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .version("v9")
+            .header(
+                HeaderName::from_static("x-proxy"),
+                HeaderValue::from_static("staging"),
+            )
+            .build()
+            .expect("failed to build client");
+
+        let welcome = client.send(&Welcome).expect("request failed");
+        assert_eq!(
+            "hello! Telraam server 2.0 is up and running",
+            welcome.status().message
+        );
+
+        let requests = server.finish();
+        assert_eq!("GET", requests[0].method);
+        assert_eq!("/v9/", requests[0].path);
+        assert_eq!(Some("secret"), requests[0].header("x-api-key"));
+        assert_eq!(Some("staging"), requests[0].header("x-proxy"));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_send_to_base_url() {
+        let server = MockServer::start(vec![MockResponse::ok(
+            r#"{"status_code": 200, "message": "ok", "cameras": []}"#,
+        )]);
+
+        let client = AsyncTelraamClient::builder("secret")
+            .base_url(server.url())
+            .build_async()
+            .expect("failed to build client");
+
+        let cameras = client
+            .send(&CamerasBySegmentId {
+                segment_id: "348917".to_string(),
+            })
+            .await
+            .expect("request failed")
+            .take_cameras()
+            .expect("bad status");
+        assert!(cameras.is_empty());
+
+        let requests = server.finish();
+        assert_eq!("/v1/cameras/segment/348917", requests[0].path);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_traffic_many() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(format!(
+                r#"{{"status_code": 200, "message": "ok", "report": [{}]}}"#,
+                report_json("2023-01-01T00:00:00.000Z"),
+            )),
+            MockResponse::status(404, r#"{"message": "segment not found"}"#),
+        ]);
+
+        let client = AsyncTelraamClient::builder("secret")
+            .base_url(server.url())
+            .retry_policy(RetryPolicy::none())
+            .build_async()
+            .expect("failed to build client");
+        let request = TrafficRequest {
+            level: crate::endpoint::TrafficLevel::Segments,
+            format: crate::endpoint::TrafficFormat::PerHour,
+            id: String::new(),
+            time_start: humantime::parse_rfc3339_weak("2023-01-01 00:00:00Z").unwrap(),
+            time_end: humantime::parse_rfc3339_weak("2023-01-02 00:00:00Z").unwrap(),
+        };
+
+        let results = client.traffic_many(&request, ["1", "2"], 2).await;
+        assert_eq!(1, results.succeeded().count());
+        assert_eq!(1, results.failed().count());
+
+        assert_eq!(2, server.finish().len());
     }
 }
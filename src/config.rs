@@ -0,0 +1,225 @@
+//! Configuration file with named profiles, each with a token, base URL, segment groups and defaults
+//!
+//! The file is TOML, for example:
+//!
+//! ```toml
+//! default_profile = "city"
+//!
+//! [profiles.city]
+//! token_command = "pass show telraam"
+//! output_format = "csv"
+//! timezone = "Europe/Brussels"
+//!
+//! [profiles.city.groups]
+//! north = [348917, 9000001463]
+//! station = [9000002156]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// The name of the profile used when none is given, and the file has no `default_profile`
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The contents of a configuration file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The profile used when none is given, [`DEFAULT_PROFILE`] if not set
+    pub default_profile: Option<String>,
+    /// The profiles, by name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Settings for one API token and the segments it is used for
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The API token
+    pub token: Option<String>,
+    /// A command that prints the API token, e.g. from a password manager, used if there is no `token`
+    pub token_command: Option<String>,
+    /// The base URL of the API, see [`crate::client::TelraamClientBuilder::base_url`]
+    pub base_url: Option<String>,
+    /// The default output format of the CLI, e.g. `json`, `ndjson` or `csv`
+    pub output_format: Option<String>,
+    /// The default time zone for local times, e.g. `Europe/Brussels`
+    pub timezone: Option<String>,
+    /// Named groups of segment identifiers, e.g. by neighborhood
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<isize>>,
+}
+
+impl Config {
+    /// The default location of the configuration file, `telraam/config.toml` in the user's configuration directory
+    ///
+    /// That is `$XDG_CONFIG_HOME` or `~/.config` on Unix, and `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config_dir.join("telraam").join("config.toml"))
+    }
+
+    /// Parse the configuration from TOML
+    pub fn from_toml(toml: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    /// Load the configuration file
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Config`] if the file cannot be read or is not valid
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let config_error = |message: String| Error::Config {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let toml = fs::read_to_string(path).map_err(|error| config_error(error.to_string()))?;
+        Self::from_toml(&toml).map_err(|error| config_error(error.message().to_string()))
+    }
+
+    /// Load the configuration file at the default path, see [`Self::default_path`], an empty configuration if there is none
+    pub fn load_default() -> Result<Self, Error> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// The profile with the name, or the default profile
+    ///
+    /// A missing default profile is empty, so that the configuration file is optional, but a profile that was asked
+    /// for by name must exist.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        match name {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::InvalidArgument {
+                    name: "profile",
+                    message: format!("no profile named {name}"),
+                }),
+            None => {
+                let name = self.default_profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+                Ok(self.profiles.get(name).cloned().unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// The API token, either from `token` or by running `token_command`
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the token command fails
+    pub fn token(&self) -> Result<Option<String>, Error> {
+        if let Some(token) = &self.token {
+            return Ok(Some(token.clone()));
+        }
+
+        let Some(command) = &self.token_command else {
+            return Ok(None);
+        };
+        let token_error = |message: String| Error::InvalidArgument {
+            name: "token_command",
+            message,
+        };
+
+        let output = if cfg!(windows) {
+            Command::new("cmd").args(["/C", command]).output()
+        } else {
+            Command::new("sh").args(["-c", command]).output()
+        }
+        .map_err(|error| token_error(error.to_string()))?;
+
+        if !output.status.success() {
+            return Err(token_error(format!(
+                "`{command}` failed with {}",
+                output.status
+            )));
+        }
+
+        let token = String::from_utf8(output.stdout)
+            .map_err(|error| token_error(error.to_string()))?
+            .trim()
+            .to_string();
+        Ok(Some(token))
+    }
+
+    /// The segments in the group with the name, if there is such a group
+    pub fn group(&self, name: &str) -> Option<&[isize]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        default_profile = "city"
+
+        [profiles.city]
+        token = "secret"
+        base_url = "https://staging.telraam-api.net"
+        output_format = "csv"
+        timezone = "Europe/Brussels"
+
+        [profiles.city.groups]
+        north = [348917, 9000001463]
+
+        [profiles.other]
+        token_command = "echo ' from-command '"
+    "#;
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::from_toml(CONFIG).expect("failed to parse config");
+
+        let city = config.profile(None).unwrap();
+        assert_eq!(Some("secret".to_string()), city.token().unwrap());
+        assert_eq!(Some("csv"), city.output_format.as_deref());
+        assert_eq!(Some(&[348917, 9000001463][..]), city.group("north"));
+        assert_eq!(None, city.group("south"));
+
+        assert!(config.profile(Some("missing")).is_err());
+        assert_eq!(Profile::default(), Config::default().profile(None).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_command() {
+        let config = Config::from_toml(CONFIG).expect("failed to parse config");
+        let other = config.profile(Some("other")).unwrap();
+        assert_eq!(Some("from-command".to_string()), other.token().unwrap());
+
+        let failing = Profile {
+            token_command: Some("exit 3".to_string()),
+            ..Profile::default()
+        };
+        assert!(failing.token().is_err());
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        assert!(Config::from_toml("[profiles.city]\ntokn = \"typo\"").is_err());
+    }
+}
@@ -0,0 +1,491 @@
+//! Coordinate reference systems used by the Telraam API, and reprojection of GeoJSON between them
+//!
+//! [`crate::endpoint::AllSegments`] returns coordinates in Belgian Lambert 72 (EPSG:31370), while the other endpoints
+//! return WGS84 longitude and latitude (EPSG:4326). Web maps usually expect Web Mercator (EPSG:3857).
+
+use std::{f64::consts::FRAC_PI_4, fmt, str::FromStr};
+
+#[cfg(feature = "clap")]
+use clap::ValueEnum;
+use geojson::{GeoJson, Geometry, Value};
+
+use crate::error::Error;
+
+/// A coordinate reference system supported by [`transform`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum Crs {
+    /// WGS84 longitude and latitude in degrees, EPSG:4326
+    #[default]
+    #[cfg_attr(feature = "clap", value(alias = "epsg:4326", alias = "4326"))]
+    Wgs84,
+    /// Belgian Lambert 72 in metres, EPSG:31370
+    #[cfg_attr(feature = "clap", value(alias = "epsg:31370", alias = "31370"))]
+    Lambert72,
+    /// Web (pseudo) Mercator in metres, EPSG:3857
+    #[cfg_attr(feature = "clap", value(alias = "epsg:3857", alias = "3857"))]
+    WebMercator,
+}
+
+impl Crs {
+    /// The EPSG code of the coordinate reference system
+    pub fn epsg(self) -> u32 {
+        match self {
+            Self::Wgs84 => 4326,
+            Self::Lambert72 => 31370,
+            Self::WebMercator => 3857,
+        }
+    }
+}
+
+impl fmt::Display for Crs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EPSG:{}", self.epsg())
+    }
+}
+
+impl FromStr for Crs {
+    type Err = Error;
+
+    fn from_str(crs: &str) -> Result<Self, Self::Err> {
+        let crs = crs.trim().to_ascii_lowercase();
+        let code = crs.strip_prefix("epsg:").unwrap_or(&crs);
+
+        match code {
+            "4326" | "wgs84" => Ok(Self::Wgs84),
+            "31370" | "lambert72" => Ok(Self::Lambert72),
+            "3857" | "web-mercator" => Ok(Self::WebMercator),
+            _ => Err(Error::InvalidArgument {
+                name: "crs",
+                message: format!("unsupported coordinate reference system: {crs}"),
+            }),
+        }
+    }
+}
+
+/// A reference ellipsoid
+struct Ellipsoid {
+    /// semi-major axis in metres
+    a: f64,
+    /// first eccentricity squared
+    e2: f64,
+}
+
+impl Ellipsoid {
+    const fn from_flattening(a: f64, inverse_flattening: f64) -> Self {
+        let f = 1.0 / inverse_flattening;
+        Self {
+            a,
+            e2: f * (2.0 - f),
+        }
+    }
+
+    fn e(&self) -> f64 {
+        self.e2.sqrt()
+    }
+
+    /// geodetic latitude and longitude in radians, on the ellipsoid surface, to earth centered earth fixed coordinates
+    fn geodetic_to_ecef(&self, lat: f64, lon: f64) -> [f64; 3] {
+        let n = self.a / (1.0 - self.e2 * lat.sin().powi(2)).sqrt();
+        [
+            n * lat.cos() * lon.cos(),
+            n * lat.cos() * lon.sin(),
+            n * (1.0 - self.e2) * lat.sin(),
+        ]
+    }
+
+    /// earth centered earth fixed coordinates to geodetic latitude and longitude in radians, the height is dropped
+    fn ecef_to_geodetic(&self, [x, y, z]: [f64; 3]) -> (f64, f64) {
+        let p = x.hypot(y);
+        let lon = y.atan2(x);
+        let mut lat = z.atan2(p * (1.0 - self.e2));
+
+        for _ in 0..10 {
+            let n = self.a / (1.0 - self.e2 * lat.sin().powi(2)).sqrt();
+            let next = (z + self.e2 * n * lat.sin()).atan2(p);
+            let done = (next - lat).abs() < 1e-14;
+            lat = next;
+            if done {
+                break;
+            }
+        }
+
+        (lat, lon)
+    }
+}
+
+const WGS84: Ellipsoid = Ellipsoid::from_flattening(6_378_137.0, 298.257_223_563);
+const INTERNATIONAL_1924: Ellipsoid = Ellipsoid::from_flattening(6_378_388.0, 297.0);
+
+/// A 7 parameter Helmert transformation, position vector convention
+struct Helmert {
+    /// translation in metres
+    t: [f64; 3],
+    /// rotation in arc seconds
+    r: [f64; 3],
+    /// scale in parts per million
+    s: f64,
+}
+
+impl Helmert {
+    fn apply(&self, [x, y, z]: [f64; 3], direction: f64) -> [f64; 3] {
+        const ARC_SECOND: f64 = std::f64::consts::PI / (180.0 * 3600.0);
+
+        let [tx, ty, tz] = self.t.map(|t| t * direction);
+        let [rx, ry, rz] = self.r.map(|r| r * ARC_SECOND * direction);
+        let scale = 1.0 + self.s * 1e-6 * direction;
+
+        [
+            tx + scale * (x - rz * y + ry * z),
+            ty + scale * (rz * x + y - rx * z),
+            tz + scale * (-ry * x + rx * y + z),
+        ]
+    }
+}
+
+/// BD72 to WGS84 (3), EPSG:15929, as used by PROJ for EPSG:31370
+const BD72_TO_WGS84: Helmert = Helmert {
+    t: [-106.8686, 52.2978, -103.7239],
+    r: [0.3366, -0.457, 1.8422],
+    s: -1.2747,
+};
+
+/// Lambert Conic Conformal with two standard parallels, EPSG method 9802
+struct LambertConformalConic {
+    ellipsoid: Ellipsoid,
+    /// latitude of the false origin in radians
+    lat_origin: f64,
+    /// longitude of the false origin in radians
+    lon_origin: f64,
+    /// first standard parallel in radians
+    lat_1: f64,
+    /// second standard parallel in radians
+    lat_2: f64,
+    /// false easting in metres
+    easting: f64,
+    /// false northing in metres
+    northing: f64,
+}
+
+impl LambertConformalConic {
+    fn m(&self, lat: f64) -> f64 {
+        lat.cos() / (1.0 - self.ellipsoid.e2 * lat.sin().powi(2)).sqrt()
+    }
+
+    fn t(&self, lat: f64) -> f64 {
+        let e = self.ellipsoid.e();
+        let e_sin = e * lat.sin();
+        (FRAC_PI_4 - lat / 2.0).tan() / ((1.0 - e_sin) / (1.0 + e_sin)).powf(e / 2.0)
+    }
+
+    /// returns n, F, and the radius at the false origin
+    fn constants(&self) -> (f64, f64, f64) {
+        let (m1, m2) = (self.m(self.lat_1), self.m(self.lat_2));
+        let (t1, t2) = (self.t(self.lat_1), self.t(self.lat_2));
+
+        let n = (m1.ln() - m2.ln()) / (t1.ln() - t2.ln());
+        let f = m1 / (n * t1.powf(n));
+        let r_origin = self.ellipsoid.a * f * self.t(self.lat_origin).max(0.0).powf(n);
+
+        (n, f, r_origin)
+    }
+
+    /// latitude and longitude in radians to easting and northing
+    fn forward(&self, lat: f64, lon: f64) -> [f64; 2] {
+        let (n, f, r_origin) = self.constants();
+        let r = self.ellipsoid.a * f * self.t(lat).powf(n);
+        let theta = n * (lon - self.lon_origin);
+
+        [
+            self.easting + r * theta.sin(),
+            self.northing + r_origin - r * theta.cos(),
+        ]
+    }
+
+    /// easting and northing to latitude and longitude in radians
+    fn inverse(&self, [easting, northing]: [f64; 2]) -> (f64, f64) {
+        let (n, f, r_origin) = self.constants();
+        let e = self.ellipsoid.e();
+
+        let dx = easting - self.easting;
+        let dy = r_origin - (northing - self.northing);
+        let r = n.signum() * dx.hypot(dy);
+        let t = (r / (self.ellipsoid.a * f)).powf(1.0 / n);
+        let theta = (n.signum() * dx).atan2(n.signum() * dy);
+
+        let mut lat = std::f64::consts::FRAC_PI_2 - 2.0 * t.atan();
+        for _ in 0..15 {
+            let e_sin = e * lat.sin();
+            let next = std::f64::consts::FRAC_PI_2
+                - 2.0 * (t * ((1.0 - e_sin) / (1.0 + e_sin)).powf(e / 2.0)).atan();
+            let done = (next - lat).abs() < 1e-14;
+            lat = next;
+            if done {
+                break;
+            }
+        }
+
+        (lat, theta / n + self.lon_origin)
+    }
+}
+
+fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+    (degrees + minutes / 60.0 + seconds / 3600.0).to_radians()
+}
+
+fn lambert72() -> LambertConformalConic {
+    LambertConformalConic {
+        ellipsoid: INTERNATIONAL_1924,
+        lat_origin: 90_f64.to_radians(),
+        lon_origin: dms(4.0, 22.0, 2.952),
+        lat_1: dms(51.0, 10.0, 0.00204),
+        lat_2: dms(49.0, 50.0, 0.00204),
+        easting: 150_000.013,
+        northing: 5_400_088.438,
+    }
+}
+
+/// Radius of the sphere used by Web Mercator
+const WEB_MERCATOR_RADIUS: f64 = 6_378_137.0;
+
+/// WGS84 `[longitude, latitude]` in degrees, to the coordinate reference system
+fn from_wgs84([lon, lat]: [f64; 2], to: Crs) -> [f64; 2] {
+    match to {
+        Crs::Wgs84 => [lon, lat],
+        Crs::WebMercator => [
+            WEB_MERCATOR_RADIUS * lon.to_radians(),
+            WEB_MERCATOR_RADIUS * (FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln(),
+        ],
+        Crs::Lambert72 => {
+            let ecef = WGS84.geodetic_to_ecef(lat.to_radians(), lon.to_radians());
+            let ecef = BD72_TO_WGS84.apply(ecef, -1.0);
+            let (lat, lon) = INTERNATIONAL_1924.ecef_to_geodetic(ecef);
+            lambert72().forward(lat, lon)
+        }
+    }
+}
+
+/// The coordinate reference system, to WGS84 `[longitude, latitude]` in degrees
+fn to_wgs84(position: [f64; 2], from: Crs) -> [f64; 2] {
+    match from {
+        Crs::Wgs84 => position,
+        Crs::WebMercator => {
+            let [x, y] = position;
+            [
+                (x / WEB_MERCATOR_RADIUS).to_degrees(),
+                (2.0 * (y / WEB_MERCATOR_RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2)
+                    .to_degrees(),
+            ]
+        }
+        Crs::Lambert72 => {
+            let (lat, lon) = lambert72().inverse(position);
+            let ecef = INTERNATIONAL_1924.geodetic_to_ecef(lat, lon);
+            let ecef = BD72_TO_WGS84.apply(ecef, 1.0);
+            let (lat, lon) = WGS84.ecef_to_geodetic(ecef);
+            [lon.to_degrees(), lat.to_degrees()]
+        }
+    }
+}
+
+/// Transform a position, `[x, y]` or `[longitude, latitude]`, between coordinate reference systems
+///
+/// The accuracy between Lambert 72 and WGS84 is around a metre, the accuracy of the BD72 to WGS84 datum shift, which
+/// is plenty for road segments.
+pub fn transform(position: [f64; 2], from: Crs, to: Crs) -> [f64; 2] {
+    if from == to {
+        return position;
+    }
+
+    from_wgs84(to_wgs84(position, from), to)
+}
+
+fn transform_position(position: &mut [f64], from: Crs, to: Crs) {
+    if let [x, y, ..] = position {
+        [*x, *y] = transform([*x, *y], from, to);
+    }
+}
+
+fn transform_bbox(bbox: &mut Option<Vec<f64>>, from: Crs, to: Crs) {
+    if let Some(values) = bbox {
+        if let [min_x, min_y, max_x, max_y] = values[..] {
+            let [min_x, min_y] = transform([min_x, min_y], from, to);
+            let [max_x, max_y] = transform([max_x, max_y], from, to);
+            *values = vec![min_x, min_y, max_x, max_y];
+        } else {
+            // 3D bounding boxes are not supported
+            *bbox = None;
+        }
+    }
+}
+
+/// Reproject all positions in the geometry, in place
+pub fn reproject_geometry(geometry: &mut Geometry, from: Crs, to: Crs) {
+    transform_bbox(&mut geometry.bbox, from, to);
+
+    match &mut geometry.value {
+        Value::Point(position) => transform_position(position, from, to),
+        Value::MultiPoint(positions) | Value::LineString(positions) => positions
+            .iter_mut()
+            .for_each(|position| transform_position(position, from, to)),
+        Value::MultiLineString(lines) | Value::Polygon(lines) => lines
+            .iter_mut()
+            .flatten()
+            .for_each(|position| transform_position(position, from, to)),
+        Value::MultiPolygon(polygons) => polygons
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|position| transform_position(position, from, to)),
+        Value::GeometryCollection(geometries) => geometries
+            .iter_mut()
+            .for_each(|geometry| reproject_geometry(geometry, from, to)),
+    }
+}
+
+/// Reproject all geometries in the GeoJSON, in place
+pub fn reproject(geo: &mut GeoJson, from: Crs, to: Crs) {
+    if from == to {
+        return;
+    }
+
+    match geo {
+        GeoJson::Geometry(geometry) => reproject_geometry(geometry, from, to),
+        GeoJson::Feature(feature) => {
+            transform_bbox(&mut feature.bbox, from, to);
+            if let Some(geometry) = &mut feature.geometry {
+                reproject_geometry(geometry, from, to);
+            }
+        }
+        GeoJson::FeatureCollection(collection) => {
+            transform_bbox(&mut collection.bbox, from, to);
+            for feature in &mut collection.features {
+                transform_bbox(&mut feature.bbox, from, to);
+                if let Some(geometry) = &mut feature.geometry {
+                    reproject_geometry(geometry, from, to);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(expected: [f64; 2], actual: [f64; 2], tolerance: f64) {
+        assert!(
+            (expected[0] - actual[0]).abs() <= tolerance
+                && (expected[1] - actual[1]).abs() <= tolerance,
+            "expected {expected:?}, got {actual:?}"
+        );
+    }
+
+    #[test]
+    fn test_lambert_conformal_conic() {
+        // the worked example from the EPSG guidance note 7-2 for method 9802, NAD27 / Texas South Central in US feet
+        let projection = LambertConformalConic {
+            ellipsoid: Ellipsoid::from_flattening(20_925_832.16, 294.978_698_2),
+            lat_origin: dms(27.0, 50.0, 0.0),
+            lon_origin: (-99_f64).to_radians(),
+            lat_1: dms(28.0, 23.0, 0.0),
+            lat_2: dms(30.0, 17.0, 0.0),
+            easting: 2_000_000.0,
+            northing: 0.0,
+        };
+
+        let projected = projection.forward(dms(28.0, 30.0, 0.0), (-96_f64).to_radians());
+        assert_close([2_963_503.91, 254_759.80], projected, 0.01);
+
+        let (lat, lon) = projection.inverse(projected);
+        assert_close([28.5, -96.0], [lat.to_degrees(), lon.to_degrees()], 1e-9);
+    }
+
+    #[test]
+    fn test_lambert72_origin() {
+        // on the central meridian, the easting is the false easting
+        let projected = lambert72().forward(dms(50.0, 30.0, 0.0), dms(4.0, 22.0, 2.952));
+        assert!((projected[0] - 150_000.013).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lambert72_epsg_example() {
+        // the worked example for Belgian Lambert 72 from the EPSG guidance note 7-2, in BD72 latitude and longitude
+        let (lat, lon) = (dms(50.0, 40.0, 46.461), dms(5.0, 48.0, 26.533));
+
+        let projected = lambert72().forward(lat, lon);
+        assert_close([251_763.20, 153_034.13], projected, 0.1);
+
+        // 1e-6 degrees is about a tenth of a metre
+        let (lat_again, lon_again) = lambert72().inverse([251_763.20, 153_034.13]);
+        assert_close(
+            [lon.to_degrees(), lat.to_degrees()],
+            [lon_again.to_degrees(), lat_again.to_degrees()],
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn test_lambert72_to_wgs84() {
+        // the EPSG example point, shifted to WGS84 with the parameters of EPSG:15929, 1e-5 degrees is about a metre
+        let wgs84 = transform([251_763.20, 153_034.13], Crs::Lambert72, Crs::Wgs84);
+        assert_close([5.808_674, 50.679_014], wgs84, 1e-5);
+
+        let lambert = transform([5.808_674, 50.679_014], Crs::Wgs84, Crs::Lambert72);
+        assert_close([251_763.20, 153_034.13], lambert, 1.0);
+    }
+
+    #[test]
+    fn test_web_mercator() {
+        let mercator = transform(
+            [4.47577215954854, 51.3021139617358],
+            Crs::Wgs84,
+            Crs::WebMercator,
+        );
+        assert_close([498_240.68, 6_674_909.18], mercator, 0.01);
+
+        let lambert = transform(mercator, Crs::WebMercator, Crs::Lambert72);
+        let wgs84 = transform(lambert, Crs::Lambert72, Crs::Wgs84);
+        assert_close([4.47577215954854, 51.3021139617358], wgs84, 1e-8);
+    }
+
+    #[test]
+    fn test_reproject() {
+        let mut geo = r#"{
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": {"oidn": 348917},
+                "geometry": {
+                    "type": "MultiLineString",
+                    "coordinates": [[[4.71129799121917, 50.8643967118925], [4.71190872556415, 50.8626435939865]]]
+                }
+            }]
+        }"#
+        .parse::<GeoJson>()
+        .unwrap();
+
+        reproject(&mut geo, Crs::Wgs84, Crs::Lambert72);
+        reproject(&mut geo, Crs::Lambert72, Crs::Wgs84);
+
+        let GeoJson::FeatureCollection(collection) = geo else {
+            panic!("expected a feature collection")
+        };
+        let geometry = collection.features[0].geometry.as_ref().unwrap();
+        let Value::MultiLineString(lines) = &geometry.value else {
+            panic!("expected a multi line string")
+        };
+        assert_close(
+            [4.71190872556415, 50.8626435939865],
+            [lines[0][1][0], lines[0][1][1]],
+            1e-8,
+        );
+    }
+
+    #[test]
+    fn test_parse_crs() {
+        assert_eq!(Crs::Lambert72, "EPSG:31370".parse::<Crs>().unwrap());
+        assert_eq!(Crs::Wgs84, "4326".parse::<Crs>().unwrap());
+        assert_eq!(Crs::WebMercator, "web-mercator".parse::<Crs>().unwrap());
+        assert!("EPSG:2154".parse::<Crs>().is_err());
+    }
+}
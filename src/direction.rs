@@ -0,0 +1,471 @@
+//! Directional analysis of the left and right counts of [`Report`]s, with compass headings from the segment geometry
+//!
+//! The counts are named as seen from the camera, which looks across the road from one side of it: `_lft` counts the
+//! road users coming from the left, see [`Report::car_lft`]. The API documents [`Camera::direction`] as the side of the
+//! road the camera is on, relative to the coordinate chain of the segment, but not which side `true` stands for. This
+//! module assumes `true` is the right of the chain. Road users coming from the left of such a camera travel along the
+//! chain, from its first to its last coordinate, so `_lft` is along the chain and `_rgt` the other way, and for a
+//! camera on the left (`false`) it is the opposite.
+//!
+//! On segment level, the API documents [`Report::direction`] as a value that makes sure the left and right counts of
+//! cameras on different sides of the street are combined properly, and it is always `1`. This module assumes the
+//! combined counts follow a camera on the right of the chain, like a [`Camera::direction`] of `true`.
+//!
+//! The geometry must be in WGS84, as returned by [`crate::endpoint::SegmentById`].
+
+use std::ops::Range;
+
+use geojson::{Geometry, Value};
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    estimate::UptimeThreshold,
+    profile::{AM_PEAK_HOURS, PM_PEAK_HOURS},
+    response::{Camera, Mode, Report, ReportInterval, SegmentResponse},
+};
+
+const COMPASS_POINTS: [&str; 8] = [
+    "north",
+    "north-east",
+    "east",
+    "south-east",
+    "south",
+    "south-west",
+    "west",
+    "north-west",
+];
+
+/// The initial bearing of the great circle from one WGS84 longitude and latitude to another, in degrees clockwise from
+/// north, from 0 up to 360
+pub fn bearing(from: [f64; 2], to: [f64; 2]) -> f64 {
+    let (lambda1, phi1) = (from[0].to_radians(), from[1].to_radians());
+    let (lambda2, phi2) = (to[0].to_radians(), to[1].to_radians());
+    let delta = lambda2 - lambda1;
+
+    let y = delta.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * delta.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// The nearest of the eight principal compass points of a bearing in degrees, e.g. `north-east`
+pub fn compass_point(bearing: f64) -> &'static str {
+    let index = ((bearing.rem_euclid(360.0) + 22.5) / 45.0) as usize % COMPASS_POINTS.len();
+    COMPASS_POINTS[index]
+}
+
+/// A direction of travel
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Heading {
+    /// The bearing in degrees clockwise from north
+    pub bearing: f64,
+    /// The nearest compass point, see [`compass_point`]
+    pub compass: &'static str,
+}
+
+impl Heading {
+    /// The heading of a bearing in degrees
+    pub fn new(bearing: f64) -> Self {
+        let bearing = bearing.rem_euclid(360.0);
+        Self {
+            bearing,
+            compass: compass_point(bearing),
+        }
+    }
+
+    /// The opposite heading
+    pub fn opposite(self) -> Self {
+        Self::new(self.bearing + 180.0)
+    }
+
+    /// A description of the heading, e.g. `towards north-east`
+    pub fn label(&self) -> String {
+        format!("towards {}", self.compass)
+    }
+}
+
+/// The headings of the left and right counts of a segment
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SegmentDirections {
+    /// The heading of the `_lft` counts
+    pub lft: Heading,
+    /// The heading of the `_rgt` counts
+    pub rgt: Heading,
+}
+
+impl SegmentDirections {
+    /// The directions of the counts seen from a side of the road, from a `LineString` or `MultiLineString` in WGS84
+    ///
+    /// `direction` is the side of the road as in [`Camera::direction`], assumed `true` for the right of the coordinate
+    /// chain, see the module docs. The chain runs from the first to the last coordinate, so that bends do not matter.
+    /// `None` for other geometries, and for segments that end where they start.
+    pub fn from_geometry(geometry: &Geometry, direction: bool) -> Option<Self> {
+        let (first, last) = match &geometry.value {
+            Value::LineString(line) => (line.first()?, line.last()?),
+            Value::MultiLineString(lines) => (lines.first()?.first()?, lines.last()?.last()?),
+            _ => return None,
+        };
+        let (&[from_x, from_y, ..], &[to_x, to_y, ..]) = (first.as_slice(), last.as_slice()) else {
+            return None;
+        };
+        if (from_x, from_y) == (to_x, to_y) {
+            return None;
+        }
+
+        // looking across the road from its right, the road users coming from the left travel along the chain
+        let along = Heading::new(bearing([from_x, from_y], [to_x, to_y]));
+        let directions = Self {
+            lft: along,
+            rgt: along.opposite(),
+        };
+        Some(if direction {
+            directions
+        } else {
+            directions.reversed()
+        })
+    }
+
+    /// The directions of the counts of a camera, from the geometry of its segment
+    pub fn from_camera(geometry: &Geometry, camera: &Camera) -> Option<Self> {
+        Self::from_geometry(geometry, camera.direction)
+    }
+
+    /// The directions of segment level reports, from the first segment in the response of
+    /// [`crate::endpoint::SegmentById`], assuming they follow a camera on the right of the chain
+    ///
+    /// # Errors
+    ///
+    /// The error of the response, or of the properties of the segment
+    pub fn from_segment(response: &SegmentResponse) -> Result<Option<Self>, Error> {
+        let Some(segment) = response.segment_features()?.next().transpose()? else {
+            return Ok(None);
+        };
+        Ok(segment
+            .geometry
+            .as_ref()
+            .and_then(|geometry| Self::from_geometry(geometry, true)))
+    }
+
+    /// The directions with left and right swapped
+    pub fn reversed(self) -> Self {
+        Self {
+            lft: self.rgt,
+            rgt: self.lft,
+        }
+    }
+}
+
+/// One of the two directions of a segment
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// The `_lft` counts
+    Lft,
+    /// The `_rgt` counts
+    Rgt,
+}
+
+/// The traffic in both directions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct DirectionalCounts {
+    /// The `_lft` count
+    pub lft: f32,
+    /// The `_rgt` count
+    pub rgt: f32,
+}
+
+impl DirectionalCounts {
+    /// The direction with the most traffic, `None` if both are equal
+    pub fn dominant(&self) -> Option<Side> {
+        if self.lft > self.rgt {
+            Some(Side::Lft)
+        } else if self.rgt > self.lft {
+            Some(Side::Rgt)
+        } else {
+            None
+        }
+    }
+
+    /// The share of the `_lft` traffic, between 0 and 1, `None` if there is no traffic
+    pub fn lft_share(&self) -> Option<f32> {
+        let total = self.lft + self.rgt;
+        (total > 0.0).then(|| self.lft / total)
+    }
+
+    /// The peak ratio, the traffic in the busiest direction divided by the other, `None` if the other has none
+    pub fn peak_ratio(&self) -> Option<f32> {
+        let (high, low) = (self.lft.max(self.rgt), self.lft.min(self.rgt));
+        (low > 0.0).then(|| high / low)
+    }
+}
+
+/// The busiest local hour of a peak period, averaged over the days
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DirectionalPeak {
+    /// The local hour, e.g. `8` for 08:00 to 09:00
+    pub hour: u32,
+    /// The mean traffic in this hour
+    pub counts: DirectionalCounts,
+    /// The direction with the most traffic
+    pub dominant: Option<Side>,
+    /// See [`DirectionalCounts::peak_ratio`]
+    pub peak_ratio: Option<f32>,
+}
+
+/// The directional analysis of the traffic of a mode on a segment
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DirectionalAnalysis {
+    /// The mode of transport
+    pub mode: Mode,
+    /// The headings of both directions, if the geometry is known
+    pub directions: Option<SegmentDirections>,
+    /// The total traffic in both directions
+    pub total: DirectionalCounts,
+    /// The busiest hour of the morning, see [`AM_PEAK_HOURS`]
+    pub am_peak: Option<DirectionalPeak>,
+    /// The busiest hour of the evening, see [`PM_PEAK_HOURS`]
+    pub pm_peak: Option<DirectionalPeak>,
+    /// How much the split between the directions flips from the morning to the evening peak
+    ///
+    /// The odds ratio `(am.lft / am.rgt) / (pm.lft / pm.rgt)`: `1` if the split is the same, far above 1 for tidal
+    /// commuting flows that travel `_lft` in the morning and return `_rgt` in the evening, and far below 1 the other way
+    /// around.
+    pub tidal_ratio: Option<f32>,
+}
+
+impl DirectionalAnalysis {
+    /// Analyse hourly reports, leaving out the hours below the threshold
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if a report is not hourly, or has an unknown time zone
+    pub fn new(
+        reports: &[Report],
+        mode: Mode,
+        directions: Option<SegmentDirections>,
+        threshold: &UptimeThreshold,
+    ) -> Result<Self, Error> {
+        let mut total = DirectionalCounts::default();
+        // the sum and the number of reports per local hour
+        let mut hours = [(DirectionalCounts::default(), 0_u32); 24];
+
+        for report in reports {
+            if report.interval != ReportInterval::Hourly {
+                return Err(Error::InvalidArgument {
+                    name: "interval",
+                    message: format!(
+                        "directional analysis needs hourly reports, got {}",
+                        report.interval.as_str()
+                    ),
+                });
+            }
+            if !threshold.is_usable(report.uptime) {
+                continue;
+            }
+            let (lft, rgt) = report.directional_count(mode);
+            total.lft += lft;
+            total.rgt += rgt;
+
+            let (sum, count) = &mut hours[report.local_hour()? as usize];
+            sum.lft += lft;
+            sum.rgt += rgt;
+            *count += 1;
+        }
+
+        let peak = |range: Range<u32>| {
+            range
+                .filter_map(|hour| {
+                    let (sum, count) = hours[hour as usize];
+                    (count > 0).then(|| {
+                        let count = count as f32;
+                        let counts = DirectionalCounts {
+                            lft: sum.lft / count,
+                            rgt: sum.rgt / count,
+                        };
+                        DirectionalPeak {
+                            hour,
+                            counts,
+                            dominant: counts.dominant(),
+                            peak_ratio: counts.peak_ratio(),
+                        }
+                    })
+                })
+                .max_by(|a, b| {
+                    (a.counts.lft + a.counts.rgt).total_cmp(&(b.counts.lft + b.counts.rgt))
+                })
+        };
+        let am_peak = peak(AM_PEAK_HOURS);
+        let pm_peak = peak(PM_PEAK_HOURS);
+
+        let tidal_ratio = match (am_peak, pm_peak) {
+            (Some(am), Some(pm)) => {
+                let (am, pm) = (am.counts, pm.counts);
+                let denominator = am.rgt * pm.lft;
+                (denominator > 0.0).then(|| am.lft * pm.rgt / denominator)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            mode,
+            directions,
+            total,
+            am_peak,
+            pm_peak,
+            tidal_ratio,
+        })
+    }
+
+    /// The heading of a direction, if the geometry is known
+    pub fn heading(&self, side: Side) -> Option<Heading> {
+        self.directions.map(|directions| match side {
+            Side::Lft => directions.lft,
+            Side::Rgt => directions.rgt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(date: &str, uptime: f32, car_lft: f32, car_rgt: f32) -> Report {
+        let car = car_lft + car_rgt;
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": {uptime}, "heavy": 0, "car": {car}, "bike": 0, "pedestrian": 0, "heavy_lft": 0, "heavy_rgt": 0, "car_lft": {car_lft}, "car_rgt": {car_rgt}, "bike_lft": 0, "bike_rgt": 0, "pedestrian_lft": 0, "pedestrian_rgt": 0, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 0}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    #[test]
+    fn test_bearing() {
+        assert!((bearing([4.7, 50.8], [4.7, 50.9]) - 0.0).abs() < 1e-9);
+        assert!((bearing([4.7, 50.8], [4.6, 50.8]) - 270.0).abs() < 0.1);
+        assert!((bearing([0.0, 0.0], [1.0, 1.0]) - 45.0).abs() < 0.01);
+
+        assert_eq!("north", compass_point(359.0));
+        assert_eq!("north-east", compass_point(30.0));
+        assert_eq!("south", compass_point(190.0));
+        assert_eq!("towards west", Heading::new(-90.0).label());
+    }
+
+    #[test]
+    fn test_segment_directions() {
+        let geometry = Geometry::new(Value::MultiLineString(vec![
+            vec![vec![4.70, 50.87], vec![4.71, 50.88]],
+            // a bend to the east does not matter
+            vec![vec![4.71, 50.88], vec![4.75, 50.88], vec![4.70, 50.89]],
+        ]));
+
+        // seen from the right of the chain, which runs north, the road users coming from the left go north
+        let right = SegmentDirections::from_geometry(&geometry, true).unwrap();
+        assert_eq!("towards north", right.lft.label());
+        assert_eq!("towards south", right.rgt.label());
+        assert_eq!(right.lft, right.reversed().rgt);
+
+        // seen from the left of the chain, it is the other way around
+        let left = SegmentDirections::from_geometry(&geometry, false).unwrap();
+        assert_eq!(right.reversed(), left);
+
+        let point = Geometry::new(Value::Point(vec![4.7, 50.87]));
+        assert_eq!(None, SegmentDirections::from_geometry(&point, true));
+    }
+
+    #[test]
+    fn test_segment_directions_from_camera() {
+        let geometry = Geometry::new(Value::LineString(vec![
+            vec![4.70, 50.87],
+            vec![4.72, 50.87],
+        ]));
+        let camera = |direction: bool| {
+            serde_json::from_value::<Camera>(serde_json::json!({
+                "instance_id": 1692, "mac": 202481587145269_usize, "user_id": 414, "segment_id": 348917,
+                "direction": direction, "status": "active", "manual": false,
+                "time_added": "2019-10-02T19:42:54.343Z", "time_end": null,
+                "last_data_package": "2021-05-02T10:56:15.402Z", "first_data_package": "2019-10-02T19:42:54.343Z",
+                "pedestrians_left": true, "pedestrians_right": true, "bikes_left": true, "bikes_right": true,
+                "cars_left": true, "cars_right": true, "is_calibration_done": "yes"
+            }))
+            .unwrap()
+        };
+
+        let right = SegmentDirections::from_camera(&geometry, &camera(true)).unwrap();
+        assert_eq!("east", right.lft.compass);
+        assert_eq!("west", right.rgt.compass);
+        let left = SegmentDirections::from_camera(&geometry, &camera(false)).unwrap();
+        assert_eq!("west", left.lft.compass);
+    }
+
+    #[test]
+    fn test_segment_directions_from_response() {
+        let json = r#"{
+            "status_code": 200,
+            "message": "ok",
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "MultiLineString", "coordinates": [[[4.70, 50.87], [4.72, 50.87]]]},
+                    "properties": {"oidn": 348917}
+                }
+            ]
+        }"#;
+        let response = serde_json::from_str::<SegmentResponse>(json).unwrap();
+
+        // segment level counts follow a camera on the right of the chain
+        let directions = SegmentDirections::from_segment(&response).unwrap().unwrap();
+        assert_eq!("east", directions.lft.compass);
+        assert_eq!("west", directions.rgt.compass);
+    }
+
+    #[test]
+    fn test_tidal_flow() {
+        let reports = [
+            // 08:00 local, into town along the segment
+            report("2024-05-06T06:00:00Z", 1.0, 20.0, 80.0),
+            report("2024-05-07T06:00:00Z", 0.5, 10.0, 40.0),
+            // 07:00 local, quieter
+            report("2024-05-06T05:00:00Z", 1.0, 10.0, 20.0),
+            // 17:00 local, back out
+            report("2024-05-06T15:00:00Z", 1.0, 60.0, 30.0),
+            // below the minimum uptime, left out
+            report("2024-05-06T16:00:00Z", 0.2, 1000.0, 0.0),
+        ];
+
+        let analysis =
+            DirectionalAnalysis::new(&reports, Mode::Car, None, &UptimeThreshold::default())
+                .unwrap();
+        assert_eq!(100.0, analysis.total.lft);
+        assert_eq!(170.0, analysis.total.rgt);
+
+        let am = analysis.am_peak.unwrap();
+        assert_eq!(8, am.hour);
+        assert_eq!(
+            DirectionalCounts {
+                lft: 15.0,
+                rgt: 60.0
+            },
+            am.counts
+        );
+        assert_eq!(Some(Side::Rgt), am.dominant);
+        assert_eq!(Some(4.0), am.peak_ratio);
+
+        let pm = analysis.pm_peak.unwrap();
+        assert_eq!(17, pm.hour);
+        assert_eq!(Some(Side::Lft), pm.dominant);
+
+        // (15 / 60) / (60 / 30)
+        assert_eq!(Some(0.125), analysis.tidal_ratio);
+        assert_eq!(None, analysis.heading(Side::Lft));
+
+        let mut daily = reports.to_vec();
+        daily[0].interval = ReportInterval::Daily;
+        assert!(matches!(
+            DirectionalAnalysis::new(&daily, Mode::Car, None, &UptimeThreshold::default()),
+            Err(Error::InvalidArgument {
+                name: "interval",
+                ..
+            })
+        ));
+    }
+}
@@ -1,15 +1,23 @@
 //! All Endpoints are intended to be used with the [`TelraamClient`]
 
-use std::{collections::HashMap, time::SystemTime};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 #[cfg(feature = "clap")]
 use clap::{Args, Parser, ValueEnum};
 use reqwest::Method;
-use serde::{Serialize, Serializer};
-
-use crate::response::{
-    CamerasResponse, Response, SegmentResponse, TrafficResponse, TrafficSnapshotResponse,
-    WelcomeResponse,
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    error::Error,
+    response::{
+        CamerasResponse, Response, SegmentResponse, TrafficResponse, TrafficSnapshotResponse,
+        WelcomeResponse,
+    },
 };
 
 /// Endpoint is a trait that defines the shape of all the API endpoints in Telraam
@@ -20,6 +28,10 @@ pub trait Endpoint {
     const PATH: &'static str;
     /// Method used for this endpoint, `GET` or `POST`
     const METHOD: Method;
+    /// If sending the same request more than once has the same effect as sending it once, see [`crate::retry::RetryPolicy`]
+    ///
+    /// All Telraam endpoints are queries, including the `POST` ones, which only use the body for parameters.
+    const IDEMPOTENT: bool = true;
 
     /// The response expected from the API, this will be deserialized from the JSON response data
     type Response: Response;
@@ -55,7 +67,7 @@ impl Endpoint for Welcome {
     type Request = ();
 }
 
-/// This HTTP POST request method can be used to retrieve the observed traffic statistics for a given segment for a given time interval (maximum 3 months at a time). Parameters for the API call can be provided in the body portion of the call, see [`TrafficRequest`].
+/// This HTTP POST request method can be used to retrieve the observed traffic statistics for a given segment for a given time interval (maximum 3 months at a time). Parameters for the API call can be provided in the body portion of the call, see [`TrafficRequest`]. Longer intervals are split into multiple calls by [`crate::client::TelraamClient::traffic`].
 #[derive(Debug)]
 #[cfg_attr(feature = "clap", derive(Parser))]
 pub struct Traffic {
@@ -63,6 +75,24 @@ pub struct Traffic {
     request: TrafficRequest,
 }
 
+impl Traffic {
+    /// Construct the endpoint for the given request
+    pub fn new(request: TrafficRequest) -> Self {
+        Self { request }
+    }
+
+    /// The request parameters
+    pub fn request(&self) -> &TrafficRequest {
+        &self.request
+    }
+}
+
+impl From<TrafficRequest> for Traffic {
+    fn from(request: TrafficRequest) -> Self {
+        Self::new(request)
+    }
+}
+
 impl Endpoint for Traffic {
     const PATH: &'static str = "reports/traffic";
     const METHOD: Method = Method::POST;
@@ -81,8 +111,8 @@ impl Endpoint for Traffic {
 pub struct TrafficRequest {
     /// the main use case is "segments" ("instance" is another option), denoting that the statistics are calculated on segment (and not individual camera, a.k.a. "instance") level
     pub level: TrafficLevel,
-    /// can only be "hourly", resulting in hourly aggregated traffic
-    pub format: String,
+    /// "per-hour" for hourly aggregated traffic, or "per-day" for daily aggregated traffic
+    pub format: TrafficFormat,
     /// the segment (or instance) identifier in question (can be found in the address of the segment from the Telraam website, e.g.: https://telraam.net/nl/location/348917)
     pub id: String,
     /// The beginning of the requested time interval (UTC)
@@ -95,6 +125,38 @@ pub struct TrafficRequest {
     pub time_end: SystemTime,
 }
 
+/// The longest interval that can be requested in one call to [`Traffic`], the API accepts at most 3 months at a time, this is the shortest 3 months (February through April)
+pub const MAX_TRAFFIC_INTERVAL: Duration = Duration::from_secs(89 * 24 * 60 * 60);
+
+impl TrafficRequest {
+    /// Split the request into consecutive requests, each covering at most `max_interval`, in order
+    ///
+    /// A request that already fits, or has an empty interval, is returned as is.
+    pub fn windows(&self, max_interval: Duration) -> Vec<Self> {
+        let max_interval = max_interval.max(Duration::from_secs(1));
+        let mut windows = Vec::new();
+        let mut time_start = self.time_start;
+
+        loop {
+            let time_end = match time_start.checked_add(max_interval) {
+                Some(time_end) if time_end < self.time_end => time_end,
+                _ => self.time_end,
+            };
+
+            windows.push(Self {
+                time_start,
+                time_end,
+                ..self.clone()
+            });
+
+            if time_end >= self.time_end {
+                return windows;
+            }
+            time_start = time_end;
+        }
+    }
+}
+
 fn format_rfc3339_millis<S: Serializer>(
     time: &SystemTime,
     serializer: S,
@@ -116,6 +178,167 @@ pub enum TrafficLevel {
     Instance,
 }
 
+/// The aggregation of the traffic statistics, see [`crate::response::ReportInterval`] for the matching interval in the reports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum TrafficFormat {
+    /// hourly aggregated traffic
+    #[default]
+    #[serde(rename = "per-hour", alias = "hourly")]
+    #[cfg_attr(feature = "clap", value(alias = "hourly"))]
+    PerHour,
+    /// daily aggregated traffic
+    #[serde(rename = "per-day", alias = "daily")]
+    #[cfg_attr(feature = "clap", value(alias = "daily"))]
+    PerDay,
+}
+
+/// This HTTP POST request method returns the traffic of all segments (or the segments in an area) at a given moment in time, in GeoJSON format. Use [`LiveTrafficSnapshot`] for the current traffic, which is much faster.
+#[derive(Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct TrafficSnapshot {
+    #[cfg_attr(feature = "clap", command(flatten))]
+    request: TrafficSnapshotRequest,
+}
+
+impl TrafficSnapshot {
+    /// Construct the endpoint for the given request
+    pub fn new(request: TrafficSnapshotRequest) -> Self {
+        Self { request }
+    }
+
+    /// The request parameters
+    pub fn request(&self) -> &TrafficSnapshotRequest {
+        &self.request
+    }
+}
+
+impl From<TrafficSnapshotRequest> for TrafficSnapshot {
+    fn from(request: TrafficSnapshotRequest) -> Self {
+        Self::new(request)
+    }
+}
+
+impl Endpoint for TrafficSnapshot {
+    const PATH: &'static str = "reports/traffic_snapshot";
+    const METHOD: Method = Method::POST;
+
+    type Response = TrafficSnapshotResponse;
+    type Request = TrafficSnapshotRequest;
+
+    fn payload(&self) -> Option<&Self::Request> {
+        Some(&self.request)
+    }
+}
+
+/// Request for a traffic snapshot at a moment in time, see [`TrafficSnapshot`]
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "clap", derive(Args))]
+pub struct TrafficSnapshotRequest {
+    /// The moment in time of the snapshot (UTC), the traffic of the hour containing this time is returned
+    #[serde(serialize_with = "format_rfc3339_millis")]
+    #[cfg_attr(feature = "clap", arg(value_parser = humantime::parse_rfc3339_weak))]
+    pub time: SystemTime,
+    /// How much detail to return for each segment
+    #[cfg_attr(feature = "clap", arg(long, value_enum, default_value_t))]
+    pub contents: SnapshotContents,
+    /// "full" for all segments, or a bounding box in WGS84 longitude and latitude, "lon_min,lat_min,lon_max,lat_max"
+    #[cfg_attr(feature = "clap", arg(long, default_value_t))]
+    pub area: Area,
+}
+
+/// Detail level of the properties of each segment in a [`TrafficSnapshot`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotContents {
+    /// only the main traffic counts
+    #[default]
+    Minimal,
+    /// all available properties
+    Full,
+}
+
+/// An area to restrict requests to
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Area {
+    /// The whole world
+    #[default]
+    Full,
+    /// Only the segments in the bounding box
+    BoundingBox(BoundingBox),
+}
+
+/// A bounding box in WGS84 longitude and latitude
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    /// western most longitude
+    pub min_lon: f64,
+    /// southern most latitude
+    pub min_lat: f64,
+    /// eastern most longitude
+    pub max_lon: f64,
+    /// northern most latitude
+    pub max_lat: f64,
+}
+
+impl fmt::Display for Area {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => f.write_str("full"),
+            Self::BoundingBox(bbox) => write!(
+                f,
+                "{},{},{},{}",
+                bbox.min_lon, bbox.min_lat, bbox.max_lon, bbox.max_lat
+            ),
+        }
+    }
+}
+
+impl FromStr for Area {
+    type Err = Error;
+
+    fn from_str(area: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: &str| Error::InvalidArgument {
+            name: "area",
+            message: format!("{message}: {area}"),
+        };
+
+        if area.trim().eq_ignore_ascii_case("full") {
+            return Ok(Self::Full);
+        }
+
+        let coordinates = area
+            .split(',')
+            .map(|coordinate| coordinate.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("expected \"full\" or \"lon_min,lat_min,lon_max,lat_max\""))?;
+
+        let [min_lon, min_lat, max_lon, max_lat] = coordinates[..] else {
+            return Err(invalid("expected 4 coordinates"));
+        };
+
+        if min_lon > max_lon || min_lat > max_lat {
+            return Err(invalid(
+                "expected the minimum before the maximum coordinates",
+            ));
+        }
+
+        Ok(Self::BoundingBox(BoundingBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        }))
+    }
+}
+
+impl Serialize for Area {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 /// This HTTP GET call is the live version of the traffic snapshot API (see documentation there). The returned GeoJSON is compiled and cached on our servers every 5 minutes, meaning that this API performs much faster than the original live option under the traffic snapshot API.
 #[derive(Debug)]
 #[cfg_attr(feature = "clap", derive(Parser))]
@@ -195,6 +418,85 @@ impl Endpoint for AllSegments {
     type Request = ();
 }
 
+/// This HTTP GET request method is used to retrieve all road segments with an active camera from the server in GeoJSON format, along with their latest traffic data. The coordinates are in WGS84 longitude and latitude.
+#[derive(Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ActiveSegments;
+
+impl Endpoint for ActiveSegments {
+    const PATH: &'static str = "segments/active";
+    const METHOD: Method = Method::GET;
+
+    type Response = SegmentResponse;
+    type Request = ();
+}
+
+/// This HTTP POST request method is used to retrieve the road segments with an active camera in the given area from the server in GeoJSON format. The returned data has the same structure as in the active segments API.
+#[derive(Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct ActiveSegmentsByArea {
+    #[cfg_attr(feature = "clap", command(flatten))]
+    request: SegmentAreaRequest,
+}
+
+impl ActiveSegmentsByArea {
+    /// Construct the endpoint for the given area
+    pub fn new(area: Area) -> Self {
+        Self {
+            request: SegmentAreaRequest { area },
+        }
+    }
+}
+
+impl Endpoint for ActiveSegmentsByArea {
+    const PATH: &'static str = "segments/active";
+    const METHOD: Method = Method::POST;
+
+    type Response = SegmentResponse;
+    type Request = SegmentAreaRequest;
+
+    fn payload(&self) -> Option<&Self::Request> {
+        Some(&self.request)
+    }
+}
+
+/// This HTTP POST request method is used to retrieve all road segments in the given area from the server in GeoJSON format, whether they have an active camera or not.
+#[derive(Debug)]
+#[cfg_attr(feature = "clap", derive(Parser))]
+pub struct SegmentsByArea {
+    #[cfg_attr(feature = "clap", command(flatten))]
+    request: SegmentAreaRequest,
+}
+
+impl SegmentsByArea {
+    /// Construct the endpoint for the given area
+    pub fn new(area: Area) -> Self {
+        Self {
+            request: SegmentAreaRequest { area },
+        }
+    }
+}
+
+impl Endpoint for SegmentsByArea {
+    const PATH: &'static str = "segments/area";
+    const METHOD: Method = Method::POST;
+
+    type Response = SegmentResponse;
+    type Request = SegmentAreaRequest;
+
+    fn payload(&self) -> Option<&Self::Request> {
+        Some(&self.request)
+    }
+}
+
+/// Request for segments in an area, see [`ActiveSegmentsByArea`] and [`SegmentsByArea`]
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "clap", derive(Args))]
+pub struct SegmentAreaRequest {
+    /// "full" for all segments, or a bounding box in WGS84 longitude and latitude, "lon_min,lat_min,lon_max,lat_max"
+    pub area: Area,
+}
+
 /// This HTTP GET request method is used to retrieve a single segments from the server in GeoJSON format.
 #[derive(Debug)]
 #[cfg_attr(feature = "clap", derive(Parser))]
@@ -203,6 +505,15 @@ pub struct SegmentById {
     segment_id: String,
 }
 
+impl SegmentById {
+    /// Construct the endpoint for the given segment
+    pub fn new(segment_id: impl Into<String>) -> Self {
+        Self {
+            segment_id: segment_id.into(),
+        }
+    }
+}
+
 impl Endpoint for SegmentById {
     const PATH: &'static str = "segments/id";
     const METHOD: Method = Method::GET;
@@ -225,7 +536,7 @@ mod tests {
     fn test_serialize_traffic() {
         let request = TrafficRequest {
             level: TrafficLevel::Segments,
-            format: String::from("per-hour"),
+            format: TrafficFormat::PerHour,
             id: "348917".to_string(),
             time_start: humantime::parse_rfc3339_weak("2020-10-30 07:00:00Z").unwrap(),
             time_end: humantime::parse_rfc3339_weak("2020-10-30 09:00:00Z").unwrap(),
@@ -236,8 +547,81 @@ mod tests {
         assert_eq!("segments", parsed["level"]);
         assert_eq!("per-hour", parsed["format"]);
         assert_eq!("348917", parsed["id"]);
+        assert_eq!(
+            TrafficFormat::PerHour,
+            serde_json::from_value::<TrafficFormat>(parsed["format"].clone()).unwrap()
+        );
         // hopefully this formatting isn't a problem, notice the spaces in the original
         assert_eq!("2020-10-30T07:00:00.000Z", parsed["time_start"]);
         assert_eq!("2020-10-30T09:00:00.000Z", parsed["time_end"]);
     }
+
+    #[test]
+    fn test_serialize_traffic_snapshot() {
+        let request = TrafficSnapshotRequest {
+            time: humantime::parse_rfc3339_weak("2020-10-30 07:00:00Z").unwrap(),
+            contents: SnapshotContents::Full,
+            area: "4.6,50.8,4.8,50.9".parse().unwrap(),
+        };
+        let json = serde_json::to_string_pretty(&request).expect("failed to serialize");
+        let parsed = serde_json::from_str::<Value>(&json).expect("failed to parse");
+
+        assert_eq!("2020-10-30T07:00:00.000Z", parsed["time"]);
+        assert_eq!("full", parsed["contents"]);
+        assert_eq!("4.6,50.8,4.8,50.9", parsed["area"]);
+    }
+
+    #[test]
+    fn test_serialize_segments_by_area() {
+        let endpoint = ActiveSegmentsByArea::new("4.6,50.8,4.8,50.9".parse().unwrap());
+        let json = serde_json::to_string(endpoint.payload().unwrap()).expect("failed to serialize");
+
+        assert_eq!(r#"{"area":"4.6,50.8,4.8,50.9"}"#, json);
+    }
+
+    #[test]
+    fn test_parse_area() {
+        assert_eq!(Area::Full, "full".parse::<Area>().unwrap());
+        assert_eq!(
+            Area::BoundingBox(BoundingBox {
+                min_lon: 4.6,
+                min_lat: 50.8,
+                max_lon: 4.8,
+                max_lat: 50.9
+            }),
+            " 4.6, 50.8 ,4.8,50.9".parse::<Area>().unwrap()
+        );
+
+        assert!("4.6,50.8,4.8".parse::<Area>().is_err());
+        assert!("4.8,50.8,4.6,50.9".parse::<Area>().is_err());
+        assert!("everywhere".parse::<Area>().is_err());
+    }
+
+    #[test]
+    fn test_traffic_windows() {
+        let request = TrafficRequest {
+            level: TrafficLevel::Segments,
+            format: TrafficFormat::PerHour,
+            id: "348917".to_string(),
+            time_start: humantime::parse_rfc3339_weak("2023-01-01 00:00:00Z").unwrap(),
+            time_end: humantime::parse_rfc3339_weak("2024-01-01 00:00:00Z").unwrap(),
+        };
+
+        let windows = request.windows(MAX_TRAFFIC_INTERVAL);
+        assert_eq!(5, windows.len());
+        assert_eq!(request.time_start, windows[0].time_start);
+        assert_eq!(request.time_end, windows[4].time_end);
+        for pair in windows.windows(2) {
+            assert_eq!(pair[0].time_end, pair[1].time_start);
+        }
+        for window in &windows {
+            let interval = window.time_end.duration_since(window.time_start).unwrap();
+            assert!(interval <= MAX_TRAFFIC_INTERVAL);
+            assert_eq!("348917", window.id);
+        }
+
+        let short = request.windows(Duration::from_secs(400 * 24 * 60 * 60));
+        assert_eq!(1, short.len());
+        assert_eq!(request.time_end, short[0].time_end);
+    }
 }
@@ -1,13 +1,273 @@
 //! Error types from the Telraam API
 
+use std::{path::PathBuf, time::Duration};
+
+use reqwest::{header::InvalidHeaderValue, StatusCode};
 use thiserror::Error;
 
 use crate::response::Status;
 
+/// Maximum number of characters of a response body kept in an error
+const SNIPPET_LEN: usize = 256;
+
 /// Various error types from the Telraam API
 #[derive(Error, Debug)]
 pub enum Error {
+    /// The API token (or another configured header) is not a valid HTTP header value
+    #[error("invalid API token: {0}")]
+    InvalidToken(#[source] InvalidHeaderValue),
+    /// A parameter for a request could not be parsed or is out of range
+    #[error("invalid {name}: {message}")]
+    InvalidArgument {
+        /// The name of the parameter
+        name: &'static str,
+        /// What is wrong with the value
+        message: String,
+    },
+    /// The configuration file could not be read or is not valid, see [`crate::config`]
+    #[error("{}: {message}", path.display())]
+    Config {
+        /// The path of the configuration file
+        path: PathBuf,
+        /// What is wrong with the file
+        message: String,
+    },
+    /// The underlying HTTP client could not be constructed
+    #[error("failed to build the HTTP client: {0}")]
+    Client(#[source] reqwest::Error),
+    /// The request payload could not be serialized to JSON
+    #[error("{path}: failed to encode the request: {source}")]
+    Encode {
+        /// The endpoint path, e.g. `reports/traffic`
+        path: String,
+        /// The serialization error
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The request could not be sent, or the response could not be read, e.g. connection errors or timeouts
+    #[error("{path}: request failed: {source}")]
+    Transport {
+        /// The endpoint path, e.g. `reports/traffic`
+        path: String,
+        /// The underlying transport error
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The properties of a GeoJSON feature could not be converted into the typed properties, see [`crate::feature`]
+    #[error("invalid feature properties: {0}")]
+    Properties(#[source] serde_json::Error),
+    /// Reports could not be written as CSV, see [`crate::export`]
+    #[error("failed to write CSV: {0}")]
+    Csv(#[source] csv::Error),
+    /// Reports could not be converted to Arrow record batches, see [`crate::export`]
+    #[cfg(feature = "arrow")]
+    #[error("failed to convert to Arrow: {0}")]
+    Arrow(#[source] arrow_schema::ArrowError),
+    /// Reports could not be written as Parquet, see [`crate::export`]
+    #[cfg(feature = "parquet")]
+    #[error("failed to write Parquet: {0}")]
+    Parquet(#[source] parquet::errors::ParquetError),
+    /// The local archive could not be read or written, see [`crate::store`]
+    #[cfg(feature = "sqlite")]
+    #[error("failed to access the SQLite store: {0}")]
+    Sqlite(#[source] rusqlite::Error),
+    /// The API rejected the token, HTTP 401 or 403
+    #[error("{path}: unauthorized, HTTP {status}, check the API token: {body}")]
+    Unauthorized {
+        /// The endpoint path, e.g. `reports/traffic`
+        path: String,
+        /// The HTTP status of the response
+        status: StatusCode,
+        /// The start of the response body
+        body: String,
+    },
+    /// The API responded with a non-success HTTP status
+    #[error("{path}: HTTP {status}: {body}")]
+    HttpStatus {
+        /// The endpoint path, e.g. `reports/traffic`
+        path: String,
+        /// The HTTP status of the response
+        status: StatusCode,
+        /// The start of the response body
+        body: String,
+        /// How long the server asked to wait before retrying, from the `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+    /// The response body could not be decoded into the expected response type
+    #[error("{path}: failed to decode the response (HTTP {status}): {source}: {snippet}")]
+    Decode {
+        /// The endpoint path, e.g. `reports/traffic`
+        path: String,
+        /// The HTTP status of the response
+        status: StatusCode,
+        /// The start of the response body that failed to decode
+        snippet: String,
+        /// The deserialization error
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The request was not sent, because it would exceed the client side rate limit, see [`crate::rate_limit`]
+    #[error("rate limited, the request would be allowed in {}", humantime::format_duration(*.wait))]
+    RateLimited {
+        /// How long until the request would be allowed
+        wait: Duration,
+    },
+    /// The request was not sent, because the daily request quota of the API token has been used up
+    #[error("daily quota of {quota} requests exceeded, resets in {}", humantime::format_duration(*.resets_in))]
+    QuotaExceeded {
+        /// The number of requests allowed per day
+        quota: u64,
+        /// Time until the quota resets
+        resets_in: Duration,
+    },
     /// An error occured on the request
     #[error("status_code:{}:{}", .0.status_code, .0.message)]
     Non200Response(Status),
 }
+
+impl Error {
+    /// The endpoint path associated to the error, if the error came from a request
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Self::Encode { path, .. }
+            | Self::Transport { path, .. }
+            | Self::Unauthorized { path, .. }
+            | Self::HttpStatus { path, .. }
+            | Self::Decode { path, .. } => Some(path),
+            Self::InvalidToken(_)
+            | Self::InvalidArgument { .. }
+            | Self::Config { .. }
+            | Self::Client(_)
+            | Self::Properties(_)
+            | Self::Csv(_)
+            | Self::RateLimited { .. }
+            | Self::QuotaExceeded { .. }
+            | Self::Non200Response(_) => None,
+            #[cfg(feature = "arrow")]
+            Self::Arrow(_) => None,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => None,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(_) => None,
+        }
+    }
+
+    /// The HTTP status (or the Telraam `status_code`) associated to the error, if any
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Unauthorized { status, .. }
+            | Self::HttpStatus { status, .. }
+            | Self::Decode { status, .. } => Some(*status),
+            Self::Transport { source, .. } => source.status(),
+            Self::Non200Response(status) => u16::try_from(status.status_code)
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok()),
+            Self::InvalidToken(_)
+            | Self::InvalidArgument { .. }
+            | Self::Config { .. }
+            | Self::Client(_)
+            | Self::Encode { .. }
+            | Self::Properties(_)
+            | Self::Csv(_)
+            | Self::RateLimited { .. }
+            | Self::QuotaExceeded { .. } => None,
+            #[cfg(feature = "arrow")]
+            Self::Arrow(_) => None,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => None,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(_) => None,
+        }
+    }
+
+    /// How long the server asked to wait before sending the request again, if it did
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::HttpStatus { retry_after, .. } => *retry_after,
+            Self::RateLimited { wait } => Some(*wait),
+            Self::QuotaExceeded { resets_in, .. } => Some(*resets_in),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the same request might succeed when sent again later, e.g. timeouts, rate limiting or server errors
+    ///
+    /// Client side rate limits are not considered retryable, the caller asked to fail fast on those.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Transport { source, .. } => source.is_timeout() || source.is_connect(),
+            Self::HttpStatus { status, .. } => is_retryable_status(*status),
+            Self::Non200Response(_) => self.status().is_some_and(is_retryable_status),
+            Self::InvalidToken(_)
+            | Self::InvalidArgument { .. }
+            | Self::Config { .. }
+            | Self::Client(_)
+            | Self::Encode { .. }
+            | Self::Properties(_)
+            | Self::Csv(_)
+            | Self::Unauthorized { .. }
+            | Self::Decode { .. }
+            | Self::RateLimited { .. }
+            | Self::QuotaExceeded { .. } => false,
+            #[cfg(feature = "arrow")]
+            Self::Arrow(_) => false,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => false,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(_) => false,
+        }
+    }
+}
+
+impl From<InvalidHeaderValue> for Error {
+    fn from(error: InvalidHeaderValue) -> Self {
+        Self::InvalidToken(error)
+    }
+}
+
+/// Statuses that are expected to be temporary
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// The start of the body, to keep errors to a reasonable size
+pub(crate) fn snippet(body: &str) -> String {
+    match body.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!("short", snippet("short"));
+
+        let long = "é".repeat(SNIPPET_LEN + 10);
+        let short = snippet(&long);
+        assert_eq!(SNIPPET_LEN + 3, short.chars().count());
+        assert!(short.ends_with("..."));
+    }
+
+    #[test]
+    fn test_non_200_response() {
+        let error = Error::Non200Response(Status {
+            status_code: 429,
+            message: "Too many requests".to_string(),
+        });
+        assert_eq!(Some(StatusCode::TOO_MANY_REQUESTS), error.status());
+        assert!(error.is_retryable());
+        assert_eq!(None, error.path());
+    }
+}
@@ -0,0 +1,334 @@
+//! Uptime-normalised estimates of the traffic from [`Report`]s, leaving out the intervals with a low uptime
+//!
+//! A camera only counts during part of each interval, see [`Report::uptime`]. The API already normalises the counts of
+//! a report to the whole interval: the example report in the [documentation for traffic][traffic], also used in the
+//! tests of [`crate::response`], has an uptime of 0.73 and 78.0821917808 heavy vehicles, which are the 57 that were
+//! counted divided by 0.73, and likewise for the other counts. So the counts of a report are its uptime-normalised
+//! estimate, dividing them by the uptime again would count the missing part of the interval twice, and the counts that
+//! were actually observed are the counts times the uptime.
+//!
+//! What is left to decide is which estimates to trust. Intervals with an uptime below a threshold are extrapolated from
+//! too little, so they are flagged as [`Quality::BelowThreshold`] and left out of the totals. The other intervals are
+//! flagged as [`Quality::Extrapolated`], or [`Quality::Complete`] if the camera counted the whole interval.
+//!
+//! [traffic]: https://documenter.getpostman.com/view/8210376/TWDRqyaV#3bb3c6bd-ea23-4329-b885-0d142403ecbb
+
+use std::{
+    ops::{Add, AddAssign, Mul},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+use crate::response::{Report, ReportInterval};
+
+/// The default of [`UptimeThreshold::min_uptime`]
+pub const DEFAULT_MIN_UPTIME: f32 = 0.5;
+
+/// The number of road users of each mode, in both directions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Counts {
+    /// Heavy vehicles
+    pub heavy: f32,
+    /// Cars
+    pub car: f32,
+    /// Two-wheelers
+    pub bike: f32,
+    /// Pedestrians
+    pub pedestrian: f32,
+}
+
+impl Counts {
+    /// The number of road users of all modes together
+    pub fn total(&self) -> f32 {
+        self.heavy + self.car + self.bike + self.pedestrian
+    }
+}
+
+impl From<&Report> for Counts {
+    fn from(report: &Report) -> Self {
+        Self {
+            heavy: report.heavy,
+            car: report.car,
+            bike: report.bike,
+            pedestrian: report.pedestrian,
+        }
+    }
+}
+
+impl Add for Counts {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            heavy: self.heavy + other.heavy,
+            car: self.car + other.car,
+            bike: self.bike + other.bike,
+            pedestrian: self.pedestrian + other.pedestrian,
+        }
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f32> for Counts {
+    type Output = Self;
+
+    fn mul(self, factor: f32) -> Self {
+        Self {
+            heavy: self.heavy * factor,
+            car: self.car * factor,
+            bike: self.bike * factor,
+            pedestrian: self.pedestrian * factor,
+        }
+    }
+}
+
+/// How an estimate was made
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    /// The camera counted during the whole interval
+    Complete,
+    /// The camera counted during part of the interval, the API extrapolated the counts to the whole interval
+    Extrapolated,
+    /// The uptime is below the threshold, the counts are not used
+    BelowThreshold,
+}
+
+/// The estimated traffic in one interval
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Estimate {
+    /// The instance identifier, `-1` for segments
+    pub instance_id: isize,
+    /// The segment identifier
+    pub segment_id: isize,
+    /// The start of the interval
+    #[serde(with = "humantime_serde")]
+    pub date: SystemTime,
+    /// The length of the interval
+    pub interval: ReportInterval,
+    /// The uptime of the report, between 0 and 1
+    pub uptime: f32,
+    /// The counts that were actually observed, the counts of the report times the uptime
+    pub observed: Counts,
+    /// The counts of the report, `None` if the uptime is below the threshold
+    pub estimated: Option<Counts>,
+    /// How the estimate was made
+    pub quality: Quality,
+}
+
+/// The estimates for a run of reports, see [`UptimeThreshold::estimate`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Estimates {
+    /// The threshold that was applied
+    pub min_uptime: f32,
+    /// One estimate per report, in the order of the reports
+    pub estimates: Vec<Estimate>,
+}
+
+impl Estimates {
+    /// The sum of the estimates, leaving out the intervals below the threshold
+    pub fn total(&self) -> Counts {
+        self.estimates
+            .iter()
+            .filter_map(|estimate| estimate.estimated)
+            .fold(Counts::default(), Add::add)
+    }
+
+    /// The sum of the observed counts, leaving out the intervals below the threshold
+    pub fn observed(&self) -> Counts {
+        self.estimates
+            .iter()
+            .filter(|estimate| estimate.estimated.is_some())
+            .fold(Counts::default(), |total, estimate| {
+                total + estimate.observed
+            })
+    }
+
+    /// The estimates that were extrapolated from part of their interval
+    pub fn extrapolated(&self) -> impl Iterator<Item = &Estimate> {
+        self.with_quality(Quality::Extrapolated)
+    }
+
+    /// The intervals with an uptime below the threshold, which have no estimate
+    pub fn below_threshold(&self) -> impl Iterator<Item = &Estimate> {
+        self.with_quality(Quality::BelowThreshold)
+    }
+
+    /// The fraction of the intervals with an estimate, between 0 and 1, `0` if there are none
+    pub fn coverage(&self) -> f32 {
+        if self.estimates.is_empty() {
+            return 0.0;
+        }
+        let usable = self.estimates.len() - self.below_threshold().count();
+        usable as f32 / self.estimates.len() as f32
+    }
+
+    fn with_quality(&self, quality: Quality) -> impl Iterator<Item = &Estimate> {
+        self.estimates
+            .iter()
+            .filter(move |estimate| estimate.quality == quality)
+    }
+}
+
+/// The minimum uptime of the intervals whose counts are used
+#[derive(Clone, Debug)]
+pub struct UptimeThreshold {
+    /// Intervals with a lower uptime get no estimate, between 0 and 1
+    pub min_uptime: f32,
+}
+
+impl Default for UptimeThreshold {
+    fn default() -> Self {
+        Self {
+            min_uptime: DEFAULT_MIN_UPTIME,
+        }
+    }
+}
+
+impl UptimeThreshold {
+    /// A threshold with a different minimum uptime
+    pub fn with_min_uptime(min_uptime: f32) -> Self {
+        Self { min_uptime }
+    }
+
+    /// If the counts of an interval with the uptime can be used, a zero uptime never can
+    pub fn is_usable(&self, uptime: f32) -> bool {
+        uptime > 0.0 && uptime >= self.min_uptime
+    }
+
+    /// The estimate for one report
+    pub fn estimate_report(&self, report: &Report) -> Estimate {
+        let counts = Counts::from(report);
+        let observed = counts * report.uptime.clamp(0.0, 1.0);
+        let estimated = self.is_usable(report.uptime).then_some(counts);
+        let quality = match estimated {
+            None => Quality::BelowThreshold,
+            Some(_) if report.uptime >= 1.0 => Quality::Complete,
+            Some(_) => Quality::Extrapolated,
+        };
+
+        Estimate {
+            instance_id: report.instance_id,
+            segment_id: report.segment_id,
+            date: report.date,
+            interval: report.interval,
+            uptime: report.uptime,
+            observed,
+            estimated,
+            quality,
+        }
+    }
+
+    /// The estimates for the reports
+    pub fn estimate<'a>(&self, reports: impl IntoIterator<Item = &'a Report>) -> Estimates {
+        Estimates {
+            min_uptime: self.min_uptime,
+            estimates: reports
+                .into_iter()
+                .map(|report| self.estimate_report(report))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(date: &str, uptime: f32, car: f32) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": {uptime}, "heavy": 2, "car": {car}, "bike": 4, "pedestrian": 1, "heavy_lft": 1, "heavy_rgt": 1, "car_lft": 0, "car_rgt": {car}, "bike_lft": 2, "bike_rgt": 2, "pedestrian_lft": 1, "pedestrian_rgt": 0, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 0}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    #[test]
+    fn test_estimate() {
+        let reports = [
+            report("2024-05-01T07:00:00Z", 1.0, 10.0),
+            report("2024-05-01T08:00:00Z", 0.5, 10.0),
+            report("2024-05-01T09:00:00Z", 0.25, 10.0),
+        ];
+
+        let estimates = UptimeThreshold::default().estimate(&reports);
+        let qualities = estimates
+            .estimates
+            .iter()
+            .map(|estimate| estimate.quality)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Quality::Complete,
+                Quality::Extrapolated,
+                Quality::BelowThreshold
+            ],
+            qualities
+        );
+
+        // the counts are already extrapolated by the API, half of them were observed
+        assert_eq!(Some(10.0), estimates.estimates[1].estimated.map(|c| c.car));
+        assert_eq!(5.0, estimates.estimates[1].observed.car);
+        assert_eq!(None, estimates.estimates[2].estimated);
+        assert_eq!(
+            vec![reports[1].date],
+            estimates
+                .extrapolated()
+                .map(|estimate| estimate.date)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, estimates.below_threshold().count());
+
+        let total = estimates.total();
+        assert_eq!(20.0, total.car);
+        assert_eq!(4.0, total.heavy);
+        assert_eq!(15.0, estimates.observed().car);
+        assert_eq!(2.0 / 3.0, estimates.coverage());
+
+        // a lower threshold uses the last hour too
+        let estimates = UptimeThreshold::with_min_uptime(0.2).estimate(&reports);
+        assert_eq!(30.0, estimates.total().car);
+        assert_eq!(17.5, estimates.observed().car);
+        assert_eq!(2, estimates.extrapolated().count());
+    }
+
+    #[test]
+    fn test_documented_report() {
+        // the example of the traffic documentation, the counts are already divided by the uptime
+        let json = r#"{"instance_id": -1, "segment_id": 348917, "date": "2020-10-30T07:00:00.000Z", "interval": "hourly", "uptime": 0.73, "heavy": 78.0821917808, "car": 619.1780821918, "bike": 263.0136986301, "pedestrian": 72.602739726, "heavy_lft": 52.0547945205, "heavy_rgt": 26.0273972603, "car_lft": 202.7397260274, "car_rgt": 416.4383561644, "bike_lft": 156.1643835616, "bike_rgt": 106.8493150685, "pedestrian_lft": 41.095890411, "pedestrian_rgt": 31.5068493151, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5}"#;
+        let report = serde_json::from_str::<Report>(json).expect("failed to parse json");
+
+        let estimate = UptimeThreshold::default().estimate_report(&report);
+        assert_eq!(Some(Counts::from(&report)), estimate.estimated);
+        // whole numbers of road users were observed
+        let observed = estimate.observed;
+        for (expected, observed) in [57.0, 452.0, 192.0, 53.0].into_iter().zip([
+            observed.heavy,
+            observed.car,
+            observed.bike,
+            observed.pedestrian,
+        ]) {
+            assert!(
+                (expected - observed).abs() < 1e-3,
+                "{expected} != {observed}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_zero_uptime() {
+        let estimates = UptimeThreshold::with_min_uptime(0.0).estimate(&[report(
+            "2024-05-01T02:00:00Z",
+            0.0,
+            0.0,
+        )]);
+        assert_eq!(Quality::BelowThreshold, estimates.estimates[0].quality);
+        assert_eq!(0.0, estimates.coverage());
+        assert_eq!(Counts::default(), estimates.total());
+    }
+}
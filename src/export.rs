@@ -0,0 +1,631 @@
+//! Export of traffic [`Report`]s and other results to CSV and newline delimited JSON, for spreadsheets and other tools
+//!
+//! With the `arrow` feature, reports can be converted to Arrow record batches, and with the `parquet` feature written
+//! to Parquet files.
+
+use std::io;
+#[cfg(feature = "arrow")]
+use std::{sync::Arc, time::SystemTime};
+
+#[cfg(feature = "arrow")]
+use arrow_array::{
+    types::Float32Type, ArrayRef, Float32Array, Int64Array, ListArray, RecordBatch, StringArray,
+    TimestampMillisecondArray, UInt64Array,
+};
+#[cfg(feature = "arrow")]
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::Timelike;
+use serde::Serialize;
+
+use crate::{error::Error, response::Report};
+
+/// Number of buckets in [`Report::car_speed_hist_0to70plus`], 10 km/h each and a last one for 70+ km/h
+pub const SPEED_BUCKETS_0TO70PLUS: usize = 8;
+
+/// Number of buckets in [`Report::car_speed_hist_0to120plus`], 5 km/h each and a last one for 120+ km/h
+pub const SPEED_BUCKETS_0TO120PLUS: usize = 25;
+
+/// Names of the columns for the buckets of a speed histogram, e.g. `car_speed_hist_0to70plus_10to20`, and a last
+/// `_overflow` column for buckets beyond these
+fn speed_bucket_columns(prefix: &str, width: usize, buckets: usize) -> Vec<String> {
+    (0..buckets)
+        .map(|bucket| {
+            let from = bucket * width;
+            if bucket + 1 == buckets {
+                format!("{prefix}_{from}plus")
+            } else {
+                format!("{prefix}_{from}to{}", from + width)
+            }
+        })
+        .chain([format!("{prefix}_overflow")])
+        .collect()
+}
+
+/// The CSV header for reports, one column per field, with the speed histograms expanded into one column per bucket
+pub fn report_columns() -> Vec<String> {
+    let mut columns = [
+        "instance_id",
+        "segment_id",
+        "date",
+        "interval",
+        "uptime",
+        "heavy",
+        "car",
+        "bike",
+        "pedestrian",
+        "heavy_lft",
+        "heavy_rgt",
+        "car_lft",
+        "car_rgt",
+        "bike_lft",
+        "bike_rgt",
+        "pedestrian_lft",
+        "pedestrian_rgt",
+        "direction",
+        "timezone",
+        "v85",
+    ]
+    .map(String::from)
+    .to_vec();
+
+    columns.extend(speed_bucket_columns(
+        "car_speed_hist_0to70plus",
+        10,
+        SPEED_BUCKETS_0TO70PLUS,
+    ));
+    columns.extend(speed_bucket_columns(
+        "car_speed_hist_0to120plus",
+        5,
+        SPEED_BUCKETS_0TO120PLUS,
+    ));
+    columns
+}
+
+/// Histogram values padded with empty cells to the number of buckets, so every row has the same columns
+///
+/// Values beyond the buckets, should the API ever return more, go into the overflow column separated by spaces, so
+/// nothing is lost.
+fn speed_bucket_values(histogram: &[f32], buckets: usize) -> impl Iterator<Item = String> + '_ {
+    let overflow = histogram
+        .get(buckets..)
+        .unwrap_or_default()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (0..buckets)
+        .map(|bucket| {
+            histogram
+                .get(bucket)
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        })
+        .chain([overflow])
+}
+
+/// The values of a report, in the order of [`report_columns`]
+pub fn report_record(report: &Report) -> Vec<String> {
+    let mut record = vec![
+        report.instance_id.to_string(),
+        report.segment_id.to_string(),
+        humantime::format_rfc3339(report.date).to_string(),
+        report.interval.as_str().to_string(),
+        report.uptime.to_string(),
+        report.heavy.to_string(),
+        report.car.to_string(),
+        report.bike.to_string(),
+        report.pedestrian.to_string(),
+        report.heavy_lft.to_string(),
+        report.heavy_rgt.to_string(),
+        report.car_lft.to_string(),
+        report.car_rgt.to_string(),
+        report.bike_lft.to_string(),
+        report.bike_rgt.to_string(),
+        report.pedestrian_lft.to_string(),
+        report.pedestrian_rgt.to_string(),
+        report.direction.to_string(),
+        report.timezone.clone(),
+        report.v85.to_string(),
+    ];
+
+    record.extend(speed_bucket_values(
+        &report.car_speed_hist_0to70plus,
+        SPEED_BUCKETS_0TO70PLUS,
+    ));
+    record.extend(speed_bucket_values(
+        &report.car_speed_hist_0to120plus,
+        SPEED_BUCKETS_0TO120PLUS,
+    ));
+    record
+}
+
+/// Rows with a fixed set of columns, for CSV and aligned text output
+pub trait Tabular {
+    /// The names of the columns
+    fn columns() -> Vec<String>;
+
+    /// The values of the row, in the order of [`Self::columns`]
+    fn record(&self) -> Vec<String>;
+}
+
+impl Tabular for Report {
+    fn columns() -> Vec<String> {
+        report_columns()
+    }
+
+    fn record(&self) -> Vec<String> {
+        report_record(self)
+    }
+}
+
+impl Tabular for LocalTimeReport<'_> {
+    fn columns() -> Vec<String> {
+        let mut columns = report_columns();
+        columns.extend(["local_start", "local_date", "local_hour"].map(String::from));
+        columns
+    }
+
+    fn record(&self) -> Vec<String> {
+        let mut record = report_record(self.report);
+        record.extend([
+            self.local_start.clone(),
+            self.local_date.clone(),
+            self.local_hour.to_string(),
+        ]);
+        record
+    }
+}
+
+/// Write the rows as CSV, with a header row
+///
+/// # Errors
+///
+/// [`Error::Csv`] if the writer fails
+pub fn write_csv<'a, W, T>(writer: W, rows: impl IntoIterator<Item = &'a T>) -> Result<(), Error>
+where
+    W: io::Write,
+    T: Tabular + 'a,
+{
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record(T::columns()).map_err(Error::Csv)?;
+    for row in rows {
+        writer.write_record(row.record()).map_err(Error::Csv)?;
+    }
+
+    writer.flush().map_err(|error| Error::Csv(error.into()))
+}
+
+/// Write the rows as a table for the terminal, with a header row and the columns aligned, numbers to the right
+pub fn write_table<'a, W, T>(mut writer: W, rows: impl IntoIterator<Item = &'a T>) -> io::Result<()>
+where
+    W: io::Write,
+    T: Tabular + 'a,
+{
+    let header = T::columns();
+    let records = rows.into_iter().map(Tabular::record).collect::<Vec<_>>();
+
+    let mut widths = header.iter().map(String::len).collect::<Vec<_>>();
+    for record in &records {
+        for (width, value) in widths.iter_mut().zip(record) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    for record in std::iter::once(&header).chain(&records) {
+        let line = record
+            .iter()
+            .zip(&widths)
+            .map(|(value, &width)| {
+                if value.parse::<f64>().is_ok() {
+                    format!("{value:>width$}")
+                } else {
+                    format!("{value:<width$}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+
+    writer.flush()
+}
+
+/// Write the reports as CSV, with a header row, see [`report_columns`]
+///
+/// # Errors
+///
+/// [`Error::Csv`] if the writer fails
+pub fn write_reports_csv<'a, W: io::Write>(
+    writer: W,
+    reports: impl IntoIterator<Item = &'a Report>,
+) -> Result<(), Error> {
+    write_csv(writer, reports)
+}
+
+/// A report with the start of its interval in the local time of the segment, see [`Report::local_start`]
+///
+/// Serializes as the report with three more fields: `local_start` (RFC 3339 with the UTC offset, so the repeated hour
+/// at the end of daylight saving time is unambiguous), `local_date` and `local_hour`.
+#[derive(Clone, Debug, Serialize)]
+pub struct LocalTimeReport<'a> {
+    /// The report, with its fields inline
+    #[serde(flatten)]
+    pub report: &'a Report,
+    /// The start of the interval in local time, e.g. `2023-10-29T02:00:00+01:00`
+    pub local_start: String,
+    /// The local date, e.g. `2023-10-29`
+    pub local_date: String,
+    /// The local hour, 0 to 23
+    pub local_hour: u32,
+}
+
+impl<'a> LocalTimeReport<'a> {
+    /// The local time of the report
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the report has an unknown time zone
+    pub fn new(report: &'a Report) -> Result<Self, Error> {
+        let local_start = report.local_start()?;
+
+        Ok(Self {
+            report,
+            local_start: local_start.to_rfc3339(),
+            local_date: local_start.date_naive().to_string(),
+            local_hour: local_start.hour(),
+        })
+    }
+
+    /// The local times of all reports
+    pub fn all(reports: impl IntoIterator<Item = &'a Report>) -> Result<Vec<Self>, Error> {
+        reports.into_iter().map(Self::new).collect()
+    }
+}
+
+/// Write the reports as CSV like [`write_reports_csv`], with `local_start`, `local_date` and `local_hour` columns
+/// appended, see [`LocalTimeReport`]
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a report has an unknown time zone, [`Error::Csv`] if the writer fails
+pub fn write_local_time_reports_csv<'a, W: io::Write>(
+    writer: W,
+    reports: impl IntoIterator<Item = &'a Report>,
+) -> Result<(), Error> {
+    write_csv(writer, &LocalTimeReport::all(reports)?)
+}
+
+/// Write each item as JSON on its own line, newline delimited JSON, e.g. for `jq` or log shippers
+///
+/// Works for reports, cameras and GeoJSON features alike, see [`crate::feature::features`].
+pub fn write_ndjson<'a, W, T>(
+    mut writer: W,
+    items: impl IntoIterator<Item = &'a T>,
+) -> io::Result<()>
+where
+    W: io::Write,
+    T: Serialize + 'a,
+{
+    for item in items {
+        serde_json::to_writer(&mut writer, item)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()
+}
+
+/// The Arrow schema of [`reports_to_record_batch`]
+///
+/// The schema is stable: columns are only ever added at the end. The `date` is a UTC timestamp in milliseconds, and the
+/// speed histograms are list columns.
+#[cfg(feature = "arrow")]
+pub fn report_schema() -> SchemaRef {
+    let float = |name: &str| Field::new(name, DataType::Float32, false);
+    let histogram =
+        |name: &str| Field::new_list(name, Field::new_list_field(DataType::Float32, true), false);
+
+    Arc::new(Schema::new(vec![
+        Field::new("instance_id", DataType::Int64, false),
+        Field::new("segment_id", DataType::Int64, false),
+        Field::new(
+            "date",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("interval", DataType::Utf8, false),
+        float("uptime"),
+        float("heavy"),
+        float("car"),
+        float("bike"),
+        float("pedestrian"),
+        float("heavy_lft"),
+        float("heavy_rgt"),
+        float("car_lft"),
+        float("car_rgt"),
+        float("bike_lft"),
+        float("bike_rgt"),
+        float("pedestrian_lft"),
+        float("pedestrian_rgt"),
+        Field::new("direction", DataType::UInt64, false),
+        Field::new("timezone", DataType::Utf8, false),
+        float("v85"),
+        histogram("car_speed_hist_0to70plus"),
+        histogram("car_speed_hist_0to120plus"),
+    ]))
+}
+
+#[cfg(feature = "arrow")]
+fn timestamp_millis(date: SystemTime) -> i64 {
+    let millis =
+        |duration: std::time::Duration| i64::try_from(duration.as_millis()).unwrap_or(i64::MAX);
+
+    match date.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => millis(since),
+        Err(before) => -millis(before.duration()),
+    }
+}
+
+/// Convert the reports into a single Arrow record batch, see [`report_schema`]
+///
+/// # Errors
+///
+/// [`Error::Arrow`] if the batch could not be constructed
+#[cfg(feature = "arrow")]
+pub fn reports_to_record_batch(reports: &[Report]) -> Result<RecordBatch, Error> {
+    let float = |value: fn(&Report) -> f32| -> ArrayRef {
+        Arc::new(reports.iter().map(value).collect::<Float32Array>())
+    };
+    let histogram = |value: fn(&Report) -> &[f32]| -> ArrayRef {
+        Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(
+            reports
+                .iter()
+                .map(|report| Some(value(report).iter().copied().map(Some))),
+        ))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(
+            reports.iter().map(|report| report.instance_id as i64),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            reports.iter().map(|report| report.segment_id as i64),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                reports.iter().map(|report| timestamp_millis(report.date)),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(StringArray::from_iter_values(
+            reports.iter().map(|report| report.interval.as_str()),
+        )),
+        float(|report| report.uptime),
+        float(|report| report.heavy),
+        float(|report| report.car),
+        float(|report| report.bike),
+        float(|report| report.pedestrian),
+        float(|report| report.heavy_lft),
+        float(|report| report.heavy_rgt),
+        float(|report| report.car_lft),
+        float(|report| report.car_rgt),
+        float(|report| report.bike_lft),
+        float(|report| report.bike_rgt),
+        float(|report| report.pedestrian_lft),
+        float(|report| report.pedestrian_rgt),
+        Arc::new(UInt64Array::from_iter_values(
+            reports.iter().map(|report| report.direction as u64),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            reports.iter().map(|report| &report.timezone),
+        )),
+        float(|report| report.v85),
+        histogram(|report| &report.car_speed_hist_0to70plus),
+        histogram(|report| &report.car_speed_hist_0to120plus),
+    ];
+
+    RecordBatch::try_new(report_schema(), columns).map_err(Error::Arrow)
+}
+
+/// Write the reports as a Parquet file, see [`report_schema`]
+///
+/// # Errors
+///
+/// [`Error::Arrow`] or [`Error::Parquet`] if the reports could not be converted or written
+#[cfg(feature = "parquet")]
+pub fn write_reports_parquet<W: io::Write + Send>(
+    writer: W,
+    reports: &[Report],
+) -> Result<(), Error> {
+    let batch = reports_to_record_batch(reports)?;
+    let mut writer = parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None)
+        .map_err(Error::Parquet)?;
+
+    writer.write(&batch).map_err(Error::Parquet)?;
+    writer.close().map_err(Error::Parquet)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_columns() {
+        let columns = report_columns();
+        assert_eq!(20 + 8 + 1 + 25 + 1, columns.len());
+        assert!(columns.contains(&"car_speed_hist_0to70plus_0to10".to_string()));
+        assert!(columns.contains(&"car_speed_hist_0to70plus_70plus".to_string()));
+        assert!(columns.contains(&"car_speed_hist_0to120plus_115to120".to_string()));
+        assert!(columns.contains(&"car_speed_hist_0to120plus_120plus".to_string()));
+        assert_eq!(
+            Some(&"car_speed_hist_0to120plus_overflow".to_string()),
+            columns.last()
+        );
+    }
+
+    #[test]
+    fn test_write_reports_csv() {
+        let reports = reports();
+
+        let mut csv = Vec::new();
+        write_reports_csv(&mut csv, &reports).expect("failed to write csv");
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("instance_id,segment_id,date,interval,uptime,"));
+        assert!(lines[1].starts_with(
+            "-1,348917,2020-10-30T07:00:00Z,hourly,0.5,1,2.5,3,4,0,1,1,1.5,1,2,2,2,1,Europe/Brussels,25.5,10,20,30,20,10,5,3,2,,"
+        ));
+        // the missing fine grained histogram is left empty
+        assert!(lines[1].ends_with(&",".repeat(SPEED_BUCKETS_0TO120PLUS + 1)));
+    }
+
+    #[test]
+    fn test_write_reports_csv_overflow() {
+        let mut reports = reports();
+        reports[0].car_speed_hist_0to70plus = (1..=10).map(|bucket| bucket as f32).collect();
+
+        let mut csv = Vec::new();
+        write_reports_csv(&mut csv, &reports).expect("failed to write csv");
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        // the buckets beyond 70+ km/h are kept in the overflow column
+        assert!(lines[1].contains(",25.5,1,2,3,4,5,6,7,8,9 10,"));
+        assert_eq!(
+            report_columns().len(),
+            lines[1].split(',').count(),
+            "every row has the same columns"
+        );
+    }
+
+    #[test]
+    fn test_write_local_time_reports_csv() {
+        let mut reports = reports();
+        let mut winter = reports[0].clone();
+        // the first hour after summer time ended, 02:00 local for the second time
+        winter.date = humantime::parse_rfc3339("2023-10-29T01:00:00Z").unwrap();
+        reports.push(winter);
+
+        let mut csv = Vec::new();
+        write_local_time_reports_csv(&mut csv, &reports).expect("failed to write csv");
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(3, lines.len());
+        assert!(lines[0]
+            .ends_with(",car_speed_hist_0to120plus_overflow,local_start,local_date,local_hour"));
+        assert!(lines[1].ends_with(",2020-10-30T08:00:00+01:00,2020-10-30,8"));
+        assert!(lines[2].ends_with(",2023-10-29T02:00:00+01:00,2023-10-29,2"));
+
+        let json = serde_json::to_value(LocalTimeReport::all(&reports).unwrap()).unwrap();
+        assert_eq!(348917, json[0]["segment_id"]);
+        assert_eq!("2020-10-30T08:00:00+01:00", json[0]["local_start"]);
+        assert_eq!(8, json[0]["local_hour"]);
+    }
+
+    fn reports() -> Vec<Report> {
+        let json = r#"
+          [
+            {"instance_id": -1, "segment_id": 348917, "date": "2020-10-30T07:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2.5, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1.5, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [10, 20, 30, 20, 10, 5, 3, 2], "car_speed_hist_0to120plus": [], "v85": 25.5}
+          ]
+        "#;
+        serde_json::from_str::<Vec<Report>>(json).expect("failed to parse json")
+    }
+
+    #[test]
+    fn test_write_table() {
+        struct Row(&'static str, f32);
+
+        impl Tabular for Row {
+            fn columns() -> Vec<String> {
+                vec!["name".to_string(), "count".to_string()]
+            }
+
+            fn record(&self) -> Vec<String> {
+                vec![self.0.to_string(), self.1.to_string()]
+            }
+        }
+
+        let mut table = Vec::new();
+        write_table(&mut table, &[Row("car", 12.5), Row("pedestrian", 3.0)]).unwrap();
+        assert_eq!(
+            "name        count\ncar          12.5\npedestrian      3\n",
+            String::from_utf8(table).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_ndjson() {
+        let geo = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "properties": {"oidn": 1}, "geometry": null},
+                {"type": "Feature", "properties": {"oidn": 2}, "geometry": null}
+            ]
+        }"#
+        .parse::<geojson::GeoJson>()
+        .unwrap();
+
+        let mut ndjson = Vec::new();
+        write_ndjson(&mut ndjson, crate::feature::features(&geo)).expect("failed to write ndjson");
+        let ndjson = String::from_utf8(ndjson).unwrap();
+
+        let lines = ndjson.lines().collect::<Vec<_>>();
+        assert_eq!(2, lines.len());
+        assert!(lines
+            .iter()
+            .all(|line| serde_json::from_str::<geojson::Feature>(line).is_ok()));
+        assert!(lines[1].contains(r#""oidn":2"#));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_reports_to_record_batch() {
+        use arrow_array::Array;
+
+        let batch = reports_to_record_batch(&reports()).expect("failed to convert");
+        assert_eq!(1, batch.num_rows());
+        assert_eq!(report_schema(), batch.schema());
+
+        let date = batch
+            .column_by_name("date")
+            .and_then(|column| column.as_any().downcast_ref::<TimestampMillisecondArray>())
+            .unwrap();
+        assert_eq!(1_604_041_200_000, date.value(0));
+
+        let histogram = batch
+            .column_by_name("car_speed_hist_0to70plus")
+            .and_then(|column| column.as_any().downcast_ref::<ListArray>())
+            .unwrap();
+        assert_eq!(8, histogram.value(0).len());
+        let fine = batch
+            .column_by_name("car_speed_hist_0to120plus")
+            .and_then(|column| column.as_any().downcast_ref::<ListArray>())
+            .unwrap();
+        assert_eq!(0, fine.value(0).len());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_reports_parquet() {
+        let path =
+            std::env::temp_dir().join(format!("telraam-reports-{}.parquet", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        write_reports_parquet(file, &reports()).expect("failed to write parquet");
+
+        let file = std::fs::File::open(&path).unwrap();
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+            .expect("failed to read parquet")
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(1, batches.len());
+        assert_eq!(reports_to_record_batch(&reports()).unwrap(), batches[0]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! Typed properties for the GeoJSON features returned by the snapshot and segment endpoints
+
+use geojson::{feature::Id, Bbox, Feature, GeoJson, Geometry, JsonObject};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// Properties of each feature in a [`crate::response::TrafficSnapshotResponse`]
+///
+/// Segments without recent data have empty values for the traffic counts, these are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotProperties {
+    /// The segment identifier
+    pub segment_id: isize,
+    /// The date and time of the last transferred data packet for the segment (UTC), as returned by the API
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub last_data_package: Option<String>,
+    /// The name of the Time zone where the segment can be found
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub timezone: Option<String>,
+    /// The date and time (UTC) of the reported interval, as returned by the API
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub date: Option<String>,
+    /// The reported interval, e.g. "hourly"
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub period: Option<String>,
+    /// between 0 and 1, the portion of the interval that was actively spent counting the traffic
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub uptime: Option<f64>,
+    /// the number of heavy vehicles
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub heavy: Option<f64>,
+    /// the number of cars
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub car: Option<f64>,
+    /// the number of two-wheelers (mainly cyclists and motorbikes)
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub bike: Option<f64>,
+    /// the number of pedestrians
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub pedestrian: Option<f64>,
+    /// the estimated car speed in km/h that 85% of all cars respect
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub v85: Option<f64>,
+    /// Any other properties, not known at the time of writing
+    #[serde(flatten)]
+    pub other: JsonObject,
+}
+
+/// Properties of each feature in a [`crate::response::SegmentResponse`]
+///
+/// [`crate::endpoint::AllSegments`] only returns the `oidn`, the other endpoints return more details.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SegmentProperties {
+    /// The segment identifier, used as `segment_id` in other API calls
+    #[serde(alias = "segment_id")]
+    pub oidn: isize,
+    /// The date and time of the first transferred data packet for the segment (UTC), as returned by the API
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub first_data_package: Option<String>,
+    /// The date and time of the last transferred data packet for the segment (UTC), as returned by the API
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub last_data_package: Option<String>,
+    /// The speed limit in km/h
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub speed: Option<f64>,
+    /// If the road is one way
+    #[serde(default)]
+    pub oneway: Option<bool>,
+    /// The type of road
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub road_type: Option<String>,
+    /// The road speed category
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub road_speed: Option<String>,
+    /// the number of pedestrians
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub pedestrian: Option<f64>,
+    /// the number of two-wheelers (mainly cyclists and motorbikes)
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub bike: Option<f64>,
+    /// the number of cars
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub car: Option<f64>,
+    /// the number of heavy vehicles
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub lorry: Option<f64>,
+    /// the car speed distribution, over `speed_buckets`
+    #[serde(default, deserialize_with = "empty_as_empty_vec")]
+    pub speed_histogram: Vec<f64>,
+    /// the buckets of the `speed_histogram`
+    #[serde(default, deserialize_with = "empty_as_empty_vec")]
+    pub speed_buckets: Vec<f64>,
+    /// Any other properties, not known at the time of writing
+    #[serde(flatten)]
+    pub other: JsonObject,
+}
+
+/// A GeoJSON feature with typed properties
+///
+/// Converting back to a [`Feature`] keeps everything but the empty strings the API uses for missing values, these are
+/// `None` in the properties and come back as `null`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedFeature<P> {
+    /// The bounding box of the feature, if any
+    pub bbox: Option<Bbox>,
+    /// The identifier of the feature, if any
+    pub id: Option<Id>,
+    /// The geometry of the feature, usually a `MultiLineString` for the road segment
+    pub geometry: Option<Geometry>,
+    /// The typed properties
+    pub properties: P,
+    /// Members of the feature other than the standard ones, if any
+    pub foreign_members: Option<JsonObject>,
+}
+
+impl<P: DeserializeOwned> TryFrom<&Feature> for TypedFeature<P> {
+    type Error = Error;
+
+    fn try_from(feature: &Feature) -> Result<Self, Self::Error> {
+        let properties = feature.properties.clone().unwrap_or_default();
+
+        Ok(Self {
+            bbox: feature.bbox.clone(),
+            id: feature.id.clone(),
+            geometry: feature.geometry.clone(),
+            properties: serde_json::from_value(Value::Object(properties))
+                .map_err(Error::Properties)?,
+            foreign_members: feature.foreign_members.clone(),
+        })
+    }
+}
+
+impl<P: Serialize> From<TypedFeature<P>> for Feature {
+    fn from(feature: TypedFeature<P>) -> Self {
+        let properties = match serde_json::to_value(feature.properties) {
+            Ok(Value::Object(properties)) => Some(properties),
+            _ => None,
+        };
+
+        Self {
+            bbox: feature.bbox,
+            geometry: feature.geometry,
+            id: feature.id,
+            properties,
+            foreign_members: feature.foreign_members,
+        }
+    }
+}
+
+impl TryFrom<&Feature> for SnapshotProperties {
+    type Error = Error;
+
+    fn try_from(feature: &Feature) -> Result<Self, Self::Error> {
+        TypedFeature::try_from(feature).map(|feature| feature.properties)
+    }
+}
+
+impl TryFrom<&Feature> for SegmentProperties {
+    type Error = Error;
+
+    fn try_from(feature: &Feature) -> Result<Self, Self::Error> {
+        TypedFeature::try_from(feature).map(|feature| feature.properties)
+    }
+}
+
+/// All features in the GeoJSON, a `FeatureCollection`, a single `Feature`, or none for a `Geometry`
+pub fn features(geo: &GeoJson) -> &[Feature] {
+    match geo {
+        GeoJson::FeatureCollection(collection) => &collection.features,
+        GeoJson::Feature(feature) => std::slice::from_ref(feature),
+        GeoJson::Geometry(_) => &[],
+    }
+}
+
+/// Iterate over the features in the GeoJSON with typed properties, e.g. [`SnapshotProperties`] or [`SegmentProperties`]
+pub fn typed_features<'a, P: DeserializeOwned + 'a>(
+    geo: &'a GeoJson,
+) -> impl Iterator<Item = Result<TypedFeature<P>, Error>> + 'a {
+    features(geo).iter().map(TypedFeature::try_from)
+}
+
+/// The API returns empty strings for missing numbers, and sometimes numbers as strings
+fn empty_as_none<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(number)) => Ok(number.as_f64()),
+        Some(Value::String(string)) if string.trim().is_empty() => Ok(None),
+        Some(Value::String(string)) => string
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        Some(other) => Err(serde::de::Error::custom(format!(
+            "expected a number or an empty string, got {other}"
+        ))),
+    }
+}
+
+/// The API returns an empty string instead of an empty list for segments without data
+fn empty_as_empty_vec<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(string)) if string.trim().is_empty() => Ok(Vec::new()),
+        Some(value) => Vec::deserialize(value).map_err(serde::de::Error::custom),
+    }
+}
+
+fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let string = Option::<String>::deserialize(deserializer)?;
+    Ok(string.filter(|string| !string.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::response::{SegmentResponse, TrafficSnapshotResponse};
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_properties() {
+        let mut json = String::new();
+
+        std::fs::File::open("tests/data/traffic_snapshot_live_2023_12_9.json")
+            .expect("failed to open test data")
+            .read_to_string(&mut json)
+            .expect("failed to read test data");
+
+        let snapshot =
+            serde_json::from_str::<TrafficSnapshotResponse>(&json).expect("failed to parse json");
+        let features = snapshot
+            .snapshot_features()
+            .expect("bad status")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to convert properties");
+        assert_eq!(5000, features.len());
+
+        let first = &features[0];
+        assert_eq!(24948, first.properties.segment_id);
+        assert_eq!(
+            Some("Europe/Brussels"),
+            first.properties.timezone.as_deref()
+        );
+        assert_eq!(None, first.properties.car);
+        assert!(first.properties.other.is_empty());
+        assert!(first.geometry.is_some());
+
+        let counted = features
+            .iter()
+            .filter(|feature| feature.properties.car.is_some())
+            .count();
+        assert_eq!(1416, counted);
+    }
+
+    #[test]
+    fn test_segment_properties() {
+        let json = r#"
+          {
+            "status_code": 200,
+            "message": "ok",
+            "type": "FeatureCollection",
+            "features": [
+              {
+                "type": "Feature",
+                "bbox": [4.71129799121917, 50.8626435939865, 4.71190872556415, 50.8643967118925],
+                "geometry": {
+                  "type": "MultiLineString",
+                  "coordinates": [[[4.71129799121917, 50.8643967118925], [4.71190872556415, 50.8626435939865]]]
+                },
+                "source": "telraam",
+                "properties": {
+                  "oidn": 348917,
+                  "first_data_package": "2019-06-26T11:00:00.000Z",
+                  "last_data_package": "2021-01-25T09:35:43.725Z",
+                  "speed": 50,
+                  "oneway": false,
+                  "road_type": "",
+                  "road_speed": "",
+                  "pedestrian": 13.1787675411836,
+                  "bike": 32.9469188529591,
+                  "car": 237.217815741306,
+                  "lorry": 70.2867602196461,
+                  "speed_histogram": [19.7681513117755, 30.7504575960952, 133.9841366687, 46.1256863941428, 4.39292251372788, 2.19646125686394],
+                  "speed_buckets": [0, 1, 2, 3, 4, 5],
+                  "uptime": 0.81
+                }
+              }
+            ]
+          }
+        "#;
+
+        let segment = serde_json::from_str::<SegmentResponse>(json).expect("failed to parse json");
+        let features = segment
+            .segment_features()
+            .expect("bad status")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to convert properties");
+        assert_eq!(1, features.len());
+
+        let properties = &features[0].properties;
+        assert_eq!(348917, properties.oidn);
+        assert_eq!(Some(50.0), properties.speed);
+        assert_eq!(Some(false), properties.oneway);
+        assert_eq!(None, properties.road_type);
+        assert_eq!(6, properties.speed_histogram.len());
+        assert_eq!(Some(&Value::from(0.81)), properties.other.get("uptime"));
+
+        assert_eq!(4, features[0].bbox.as_ref().map_or(0, Vec::len));
+
+        // only the empty strings change converting back, they become null
+        let feature = Feature::from(features[0].clone());
+        assert_eq!(features[0].bbox, feature.bbox);
+        assert_eq!(
+            Some(&Value::from("telraam")),
+            feature
+                .foreign_members
+                .as_ref()
+                .and_then(|members| members.get("source"))
+        );
+        assert_eq!(Some(&Value::Null), feature.property("road_type"));
+        let again = TypedFeature::<SegmentProperties>::try_from(&feature).unwrap();
+        assert_eq!(features[0], again);
+    }
+
+    #[test]
+    fn test_segment_without_data() {
+        let properties = serde_json::from_str::<SegmentProperties>(
+            r#"{"oidn": 348917, "car": "", "speed_histogram": "", "speed_buckets": ""}"#,
+        )
+        .expect("failed to parse json");
+        assert_eq!(None, properties.car);
+        assert!(properties.speed_histogram.is_empty());
+        assert!(properties.speed_buckets.is_empty());
+    }
+
+    #[test]
+    fn test_missing_segment_id() {
+        let feature = Feature {
+            bbox: None,
+            geometry: None,
+            id: None,
+            properties: None,
+            foreign_members: None,
+        };
+
+        let error = SnapshotProperties::try_from(&feature).expect_err("segment_id is required");
+        assert!(matches!(error, Error::Properties(_)));
+    }
+}
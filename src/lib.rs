@@ -1,9 +1,27 @@
 //! Telraam library for working with the Telraam API.
 
+pub mod aggregate;
 pub mod client;
+pub mod config;
+pub mod crs;
+pub mod direction;
 pub mod endpoint;
 pub mod error;
+pub mod estimate;
+pub mod export;
+pub mod feature;
+pub mod metrics;
+pub mod profile;
+pub mod rate_limit;
 pub mod response;
+pub mod retry;
+pub mod speed;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod time_range;
+
+#[cfg(test)]
+mod test_util;
 
 /// Version of the Telraam API this library supports
 pub const VER: &str = "v1";
@@ -2,30 +2,313 @@
 
 #![allow(clippy::print_stdout)]
 
-use clap::{Parser, Subcommand};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
-use telraam::{client::TelraamClient, endpoint, response::Response};
+#[cfg(feature = "parquet")]
+use std::io::IsTerminal;
+
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use geojson::GeoJson;
+use serde::Serialize;
+
+#[cfg(feature = "sqlite")]
+use telraam::store::{self, Store};
+use telraam::{
+    aggregate::{self, Aggregate, Period},
+    client::{self, TelraamClient},
+    config::{Config, Profile},
+    crs::{self, Crs},
+    endpoint::{self, TrafficFormat, TrafficLevel, TrafficRequest},
+    estimate::{self, UptimeThreshold},
+    export::{self, LocalTimeReport, Tabular},
+    feature,
+    metrics::{self, Breakdown},
+    profile::{self, ProfileRow},
+    rate_limit::{DailyUsage, RateLimit, RateLimiter},
+    response::{Report, Response},
+    retry::RetryPolicy,
+    time_range::{TimeRange, TimeRangeArgs},
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// The API token, overrides the token of the profile
     #[arg(short = 't', env = "TELRAAM_TOKEN", hide_env_values = true)]
-    telraam_token: String,
+    telraam_token: Option<String>,
+
+    /// Configuration file with profiles, defaults to `telraam/config.toml` in the user's configuration directory
+    #[arg(long, env = "TELRAAM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The profile to use from the configuration file
+    #[arg(long, env = "TELRAAM_PROFILE")]
+    profile: Option<String>,
+
+    /// Base URL of the Telraam API, e.g. a proxy or staging host, overrides the base URL of the profile
+    #[arg(long, env = "TELRAAM_BASE_URL")]
+    base_url: Option<String>,
+
+    /// Timeout for each request to the API, e.g. `30s`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Number of times a failed request is retried, with exponential backoff
+    #[arg(long, default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: u32,
+
+    /// Limit the number of requests per second sent to the API
+    #[arg(long)]
+    requests_per_second: Option<f64>,
+
+    /// Fail instead of sending more requests than the daily quota of the token
+    #[arg(long)]
+    daily_quota: Option<u64>,
+
+    /// File to keep track of the number of requests made today, to respect `--daily-quota` across runs
+    #[arg(long, requires = "daily_quota")]
+    usage_file: Option<PathBuf>,
+
+    /// Number of requests sent at once when fetching the traffic of several segments, within the rate limits
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Time zone for calendar days, weeks and months, e.g. `Europe/Brussels`, overrides the time zone of the profile, defaults to the time zone of each segment
+    #[arg(long, global = true)]
+    timezone: Option<Tz>,
+
+    /// Reproject the coordinates of segments and snapshots, e.g. `wgs84` or `epsg:31370`
+    #[arg(long, value_enum)]
+    crs: Option<Crs>,
+
+    /// The format of the output, overrides the output format of the profile, defaults to `json`
+    #[arg(long, value_enum, global = true)]
+    output_format: Option<OutputFormat>,
+
+    /// Write the output to a file instead of stdout, required for `parquet` unless stdout is redirected
+    #[arg(long, global = true)]
+    output: Option<PathBuf>,
+
+    /// Add the local start time, date and hour of each traffic report, in the time zone of its segment
+    #[arg(long, global = true)]
+    local_time: bool,
 
     #[command(subcommand)]
     command: Commands,
 }
 
+/// How to print the results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Pretty printed JSON
+    #[default]
+    Json,
+    /// Newline delimited JSON, one report, camera or GeoJSON feature per line
+    Ndjson,
+    /// CSV with a header row, only for the reports from `traffic`, for `summary`, and for the hours of `profile`
+    Csv,
+    /// A table with aligned columns, only for the reports from `traffic`, for `summary` and for `profile`
+    Table,
+    /// Parquet file, only for the reports from `traffic`
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     Welcome(endpoint::Welcome),
-    Traffic(endpoint::Traffic),
+    Traffic(TrafficArgs),
+    TrafficSnapshot(endpoint::TrafficSnapshot),
     LiveTrafficSnapshot(endpoint::LiveTrafficSnapshot),
     AllAvailableCameras(endpoint::AllAvailableCameras),
     CamerasBySegmentId(endpoint::CamerasBySegmentId),
     CameraByMacId(endpoint::CameraByMacId),
     AllSegments(endpoint::AllSegments),
+    ActiveSegments(endpoint::ActiveSegments),
+    ActiveSegmentsByArea(endpoint::ActiveSegmentsByArea),
+    SegmentsByArea(endpoint::SegmentsByArea),
     SegmentById(endpoint::SegmentById),
+    /// Print the modal share and active travel metrics of a segment, from its hourly traffic with enough uptime
+    Summary(SummaryArgs),
+    /// Print the typical weekday and weekend traffic of a segment per local hour, with the morning and evening peaks, CSV has the hours only
+    Profile(ProfileArgs),
+    /// Archive the hourly traffic of segments in a local SQLite database, fetching what is new and the last 48 hours again
+    #[cfg(feature = "sqlite")]
+    Sync(SyncArgs),
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug, clap::Args)]
+struct SyncArgs {
+    /// The SQLite database, created if it does not exist
+    #[arg(long, default_value = "telraam.db")]
+    database: PathBuf,
+
+    /// Where to start for segments that were never synced (UTC), defaults to the longest interval of a single request
+    #[arg(long, value_parser = humantime::parse_rfc3339_weak)]
+    since: Option<SystemTime>,
+
+    /// Also store the cameras and details of the segments
+    #[arg(long)]
+    metadata: bool,
+
+    /// The segments to sync
+    #[arg(required = true)]
+    segment_ids: Vec<isize>,
+}
+
+/// This HTTP POST request method can be used to retrieve the observed traffic statistics for segments for a given time interval. Intervals longer than the API allows are split into multiple calls.
+#[derive(Debug, clap::Args)]
+struct TrafficArgs {
+    /// the main use case is "segments" ("instance" is another option), denoting that the statistics are calculated on segment (and not individual camera, a.k.a. "instance") level
+    level: TrafficLevel,
+    /// "per-hour" for hourly aggregated traffic, or "per-day" for daily aggregated traffic
+    format: TrafficFormat,
+    /// the segment (or instance) identifier, a comma separated list of them, or names of groups in the profile
+    id: String,
+    #[command(flatten)]
+    range: TimeRangeArgs,
+    /// Roll the reports up into daily, weekly or monthly totals per segment, in the local time of the segment, not for parquet
+    #[arg(long, value_enum)]
+    aggregate: Option<Period>,
+}
+
+#[derive(Debug, clap::Args)]
+struct SummaryArgs {
+    /// the segment identifier
+    segment_id: String,
+    #[command(flatten)]
+    range: TimeRangeArgs,
+    /// Compute the metrics per hour, per local day of the segment, or over the whole time range
+    #[arg(long, value_enum, default_value_t)]
+    by: Breakdown,
+    /// Leave out the hours with a lower uptime, between 0 and 1
+    #[arg(long, default_value_t = estimate::DEFAULT_MIN_UPTIME)]
+    min_uptime: f32,
+}
+
+#[derive(Debug, clap::Args)]
+struct ProfileArgs {
+    /// the segment identifier
+    segment_id: String,
+    #[command(flatten)]
+    range: TimeRangeArgs,
+    /// Leave out the hours with a lower uptime, between 0 and 1
+    #[arg(long, default_value_t = estimate::DEFAULT_MIN_UPTIME)]
+    min_uptime: f32,
+}
+
+/// Prints the results of all commands in the requested format
+struct Output {
+    format: OutputFormat,
+    crs: Option<Crs>,
+    local_time: bool,
+    path: Option<PathBuf>,
+}
+
+impl Output {
+    /// The file to write to, or stdout
+    fn writer(&self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match &self.path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        })
+    }
+
+    fn unsupported(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Err(format!("{:?} output is not supported for this command", self.format).into())
+    }
+
+    /// Print a list, e.g. of cameras, one item per line for NDJSON
+    fn list<T: Serialize>(&self, items: &[T]) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            OutputFormat::Json => {
+                writeln!(self.writer()?, "{}", serde_json::to_string_pretty(items)?)?
+            }
+            OutputFormat::Ndjson => export::write_ndjson(self.writer()?, items)?,
+            _ => self.unsupported()?,
+        }
+        Ok(())
+    }
+
+    fn reports(&self, reports: &[Report]) -> Result<(), Box<dyn std::error::Error>> {
+        if self.local_time {
+            return match self.format {
+                OutputFormat::Csv => Ok(export::write_local_time_reports_csv(
+                    self.writer()?,
+                    reports,
+                )?),
+                OutputFormat::Json | OutputFormat::Ndjson => {
+                    self.list(&LocalTimeReport::all(reports)?)
+                }
+                OutputFormat::Table => Ok(export::write_table(
+                    self.writer()?,
+                    &LocalTimeReport::all(reports)?,
+                )?),
+                #[cfg(feature = "parquet")]
+                OutputFormat::Parquet => {
+                    Err("--local-time is not supported for parquet output".into())
+                }
+            };
+        }
+
+        match self.format {
+            OutputFormat::Csv => export::write_reports_csv(self.writer()?, reports)?,
+            OutputFormat::Table => export::write_table(self.writer()?, reports)?,
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => {
+                if self.path.is_none() && io::stdout().is_terminal() {
+                    return Err(
+                        "refusing to write parquet to a terminal, use --output or redirect stdout"
+                            .into(),
+                    );
+                }
+                export::write_reports_parquet(self.writer()?, reports)?
+            }
+            _ => self.list(reports)?,
+        }
+        Ok(())
+    }
+
+    /// Print the totals of the reports per period, with the end of each period
+    fn aggregates(&self, aggregates: &[Aggregate]) -> Result<(), Box<dyn std::error::Error>> {
+        if self.local_time {
+            return Err("--local-time is not supported with --aggregate".into());
+        }
+        self.table(aggregates)
+    }
+
+    /// Print rows with fixed columns, e.g. metrics
+    fn table<T: Tabular + Serialize>(&self, rows: &[T]) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            OutputFormat::Csv => export::write_csv(self.writer()?, rows)?,
+            OutputFormat::Table => export::write_table(self.writer()?, rows)?,
+            _ => self.list(rows)?,
+        }
+        Ok(())
+    }
+
+    /// Print GeoJSON in the requested coordinate reference system, one feature per line for NDJSON
+    fn geojson(&self, mut geo: GeoJson, source: Crs) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(crs) = self.crs {
+            crs::reproject(&mut geo, source, crs);
+        }
+
+        match self.format {
+            OutputFormat::Json => {
+                writeln!(self.writer()?, "{}", serde_json::to_string_pretty(&geo)?)?
+            }
+            OutputFormat::Ndjson => export::write_ndjson(self.writer()?, feature::features(&geo))?,
+            _ => self.unsupported()?,
+        }
+        Ok(())
+    }
 }
 
 fn welcome(
@@ -37,85 +320,351 @@ fn welcome(
     Ok(())
 }
 
-fn traffic(
-    client: &TelraamClient,
-    request: &endpoint::Traffic,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let reports = client.send(request)?.take_reports()?;
-    println!("{}", serde_json::to_string_pretty(&reports)?);
+#[cfg(feature = "sqlite")]
+fn sync(client: &TelraamClient, args: &SyncArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut store = Store::open(&args.database)?;
+    // only complete hours
+    let until = store::truncate_to_hour(SystemTime::now());
+    let since = args
+        .since
+        .unwrap_or_else(|| until - endpoint::MAX_TRAFFIC_INTERVAL);
+    let mut failed = 0;
+
+    for &segment_id in &args.segment_ids {
+        let result = store
+            .sync_traffic(client, segment_id, since, until)
+            .and_then(|reports| {
+                if args.metadata {
+                    let cameras = client
+                        .send(&endpoint::CamerasBySegmentId {
+                            segment_id: segment_id.to_string(),
+                        })?
+                        .take_cameras()?;
+                    store.upsert_cameras(&cameras)?;
+
+                    let segments =
+                        client.send(&endpoint::SegmentById::new(segment_id.to_string()))?;
+                    let segments = segments
+                        .segment_features()?
+                        .collect::<Result<Vec<_>, _>>()?;
+                    store.upsert_segments(&segments)?;
+                }
+                Ok(reports)
+            });
+
+        match result {
+            Ok(reports) => println!("segment {segment_id}: {reports} reports synced"),
+            Err(error) => {
+                failed += 1;
+                eprintln!("segment {segment_id}: sync failed: {error}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!(
+            "{failed} of {} segments failed to sync",
+            args.segment_ids.len()
+        )
+        .into());
+    }
     Ok(())
 }
 
-fn live_traffic_snapshot(
-    client: &TelraamClient,
-    request: &endpoint::LiveTrafficSnapshot,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let snapshot = client.send(request)?.take_snapshot()?;
-    println!("{}", serde_json::to_string_pretty(&snapshot)?);
-    Ok(())
+/// The time zone from the flag, or the profile, `None` for the time zone of each segment
+fn timezone(args: &Args, profile: &Profile) -> Result<Option<Tz>, Box<dyn std::error::Error>> {
+    match (args.timezone, &profile.timezone) {
+        (Some(tz), _) => Ok(Some(tz)),
+        (None, Some(tz)) => {
+            Ok(Some(tz.parse::<Tz>().map_err(|error| {
+                format!("invalid timezone in the profile: {error}")
+            })?))
+        }
+        (None, None) => Ok(None),
+    }
 }
 
-fn all_available_cameras(
-    client: &TelraamClient,
-    request: &endpoint::AllAvailableCameras,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let cameras = client.send(request)?.take_cameras()?;
-    println!("{}", serde_json::to_string_pretty(&cameras)?);
-    Ok(())
+/// The time range of a command, calendar boundaries are in the time zone, or in the time zone of each segment if there
+/// is none
+struct Window {
+    range: TimeRange,
+    now: SystemTime,
+    timezone: Option<Tz>,
+}
+
+impl Window {
+    fn new(
+        range: &TimeRangeArgs,
+        timezone: Option<Tz>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            range: range.range().ok_or("no time range")?,
+            now: SystemTime::now(),
+            timezone,
+        })
+    }
+
+    /// The start and end to request, wide enough for any time zone if the time zone of the segment is not known yet
+    fn request(&self) -> Result<(SystemTime, SystemTime), Box<dyn std::error::Error>> {
+        Ok(match self.timezone {
+            Some(tz) => self.range.resolve(self.now, &tz)?,
+            None => self.range.resolve_any_timezone(self.now)?,
+        })
+    }
+
+    /// The reports in the time range, narrowed down in the time zone of their segment if it was not known
+    fn retain(&self, mut reports: Vec<Report>) -> Result<Vec<Report>, Box<dyn std::error::Error>> {
+        if self.timezone.is_none() {
+            self.range.retain_reports(self.now, &mut reports)?;
+        }
+        Ok(reports)
+    }
 }
 
-fn cameras_by_segmant_id(
+/// The traffic of the segments in the `id`, a comma separated list of segment ids or names of groups in the profile
+///
+/// Several segments are fetched concurrently, the segments that failed are listed, after printing the reports of the
+/// segments that succeeded.
+fn traffic(
     client: &TelraamClient,
-    request: &endpoint::CamerasBySegmentId,
+    args: &TrafficArgs,
+    profile: &Profile,
+    output: &Output,
+    settings: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let cameras = client.send(request)?.take_cameras()?;
-    println!("{}", serde_json::to_string_pretty(&cameras)?);
+    let window = Window::new(&args.range, timezone(settings, profile)?)?;
+    let (time_start, time_end) = window.request()?;
+    let request = TrafficRequest {
+        level: args.level.clone(),
+        format: args.format,
+        id: args.id.clone(),
+        time_start,
+        time_end,
+    };
+
+    let print_reports = |reports: Vec<Report>| {
+        let reports = window.retain(reports)?;
+        match args.aggregate {
+            Some(period) => output.aggregates(&aggregate::aggregate(&reports, period)?),
+            None => output.reports(&reports),
+        }
+    };
+
+    let mut ids = Vec::new();
+    for id in request
+        .id
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+    {
+        match profile.group(id) {
+            Some(group) => ids.extend(group.iter().map(ToString::to_string)),
+            None => ids.push(id.to_string()),
+        }
+    }
+
+    if let [id] = &ids[..] {
+        let request = TrafficRequest {
+            id: id.clone(),
+            ..request.clone()
+        };
+        return print_reports(client.traffic(&request)?);
+    }
+
+    let results = client.traffic_many(&request, ids, settings.concurrency);
+    for (id, error) in &results.failures {
+        eprintln!("{id}: failed: {error}");
+    }
+
+    let failed = results.failures.len();
+    let total = failed + results.reports.len();
+    print_reports(results.merged_reports())?;
+
+    if failed > 0 {
+        return Err(format!("{failed} of {total} ids failed").into());
+    }
     Ok(())
 }
 
-fn camera_by_mac_id(
+fn summary(
     client: &TelraamClient,
-    request: &endpoint::CameraByMacId,
+    args: &SummaryArgs,
+    profile: &Profile,
+    output: &Output,
+    settings: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let cameras = client.send(request)?.take_cameras()?;
-    println!("{}", serde_json::to_string_pretty(&cameras)?);
-    Ok(())
+    let window = Window::new(&args.range, timezone(settings, profile)?)?;
+    let (time_start, time_end) = window.request()?;
+    let reports = window.retain(client.traffic(&TrafficRequest {
+        level: TrafficLevel::Segments,
+        format: TrafficFormat::PerHour,
+        id: args.segment_id.clone(),
+        time_start,
+        time_end,
+    })?)?;
+
+    let threshold = UptimeThreshold::with_min_uptime(args.min_uptime);
+    output.table(&metrics::modal_shares(&reports, args.by, &threshold)?)
 }
 
-fn all_segments(
+fn hourly_profile(
     client: &TelraamClient,
-    request: &endpoint::AllSegments,
+    args: &ProfileArgs,
+    profile: &Profile,
+    output: &Output,
+    settings: &Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let segments = client.send(request)?.take_segments()?;
-    println!("{}", serde_json::to_string_pretty(&segments)?);
+    let window = Window::new(&args.range, timezone(settings, profile)?)?;
+    let (time_start, time_end) = window.request()?;
+    let reports = window.retain(client.traffic(&TrafficRequest {
+        level: TrafficLevel::Segments,
+        format: TrafficFormat::PerHour,
+        id: args.segment_id.clone(),
+        time_start,
+        time_end,
+    })?)?;
+
+    let threshold = UptimeThreshold::with_min_uptime(args.min_uptime);
+    let profiles = profile::profiles(&reports, &threshold)?;
+
+    match output.format {
+        // the peaks follow the hours as a second table
+        OutputFormat::Table => {
+            let mut writer = output.writer()?;
+            export::write_table(&mut writer, &ProfileRow::rows(&profiles))?;
+            writeln!(writer)?;
+            let peaks = profiles
+                .iter()
+                .flat_map(|profile| [&profile.am_peak, &profile.pm_peak])
+                .flatten();
+            export::write_table(&mut writer, peaks)?;
+        }
+        // a single CSV table, use JSON for the peaks
+        OutputFormat::Csv => output.table(&ProfileRow::rows(&profiles))?,
+        _ => output.list(&profiles)?,
+    }
     Ok(())
 }
 
-fn segment_by_id(
+fn run(
     client: &TelraamClient,
-    request: &endpoint::SegmentById,
+    args: &Args,
+    profile: &Profile,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let segments = client.send(request)?.take_segments()?;
-    println!("{}", serde_json::to_string_pretty(&segments)?);
+    let format = match (args.output_format, &profile.output_format) {
+        (Some(format), _) => format,
+        (None, Some(format)) => OutputFormat::from_str(format, true)
+            .map_err(|error| format!("invalid output_format in the profile: {error}"))?,
+        (None, None) => OutputFormat::default(),
+    };
+    let output = Output {
+        format,
+        crs: args.crs,
+        local_time: args.local_time,
+        path: args.output.clone(),
+    };
+
+    match &args.command {
+        Commands::Welcome(request) => welcome(client, request)?,
+        Commands::Traffic(traffic_args) => traffic(client, traffic_args, profile, &output, args)?,
+        Commands::Summary(summary_args) => summary(client, summary_args, profile, &output, args)?,
+        Commands::Profile(profile_args) => {
+            hourly_profile(client, profile_args, profile, &output, args)?
+        }
+        Commands::TrafficSnapshot(request) => {
+            output.geojson(client.send(request)?.take_snapshot()?, Crs::Wgs84)?
+        }
+        Commands::LiveTrafficSnapshot(request) => {
+            output.geojson(client.send(request)?.take_snapshot()?, Crs::Wgs84)?
+        }
+        Commands::AllAvailableCameras(request) => {
+            output.list(&client.send(request)?.take_cameras()?)?
+        }
+        Commands::CamerasBySegmentId(request) => {
+            output.list(&client.send(request)?.take_cameras()?)?
+        }
+        Commands::CameraByMacId(request) => output.list(&client.send(request)?.take_cameras()?)?,
+        // only the oidn and coordinates in Belgian Lambert 72 are returned for all segments
+        Commands::AllSegments(request) => {
+            output.geojson(client.send(request)?.take_segments()?, Crs::Lambert72)?
+        }
+        Commands::ActiveSegments(request) => {
+            output.geojson(client.send(request)?.take_segments()?, Crs::Wgs84)?
+        }
+        Commands::ActiveSegmentsByArea(request) => {
+            output.geojson(client.send(request)?.take_segments()?, Crs::Wgs84)?
+        }
+        Commands::SegmentsByArea(request) => {
+            output.geojson(client.send(request)?.take_segments()?, Crs::Wgs84)?
+        }
+        Commands::SegmentById(request) => {
+            output.geojson(client.send(request)?.take_segments()?, Crs::Wgs84)?
+        }
+        #[cfg(feature = "sqlite")]
+        Commands::Sync(sync_args) => sync(client, sync_args)?,
+    }
+
     Ok(())
 }
 
+fn rate_limiter(args: &Args) -> Result<Option<RateLimiter>, std::io::Error> {
+    if args.requests_per_second.is_none() && args.daily_quota.is_none() {
+        return Ok(None);
+    }
+
+    let limit = RateLimit {
+        per_second: args.requests_per_second,
+        daily_quota: args.daily_quota,
+        ..RateLimit::default()
+    };
+    let usage = match &args.usage_file {
+        Some(usage_file) => DailyUsage::load(usage_file)?,
+        None => DailyUsage::default(),
+    };
+
+    Ok(Some(RateLimiter::with_usage(limit, usage)))
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let api_token = &args.telraam_token as &str;
-    let client = TelraamClient::new(api_token)?;
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    let profile = config.profile(args.profile.as_deref())?;
 
-    match &args.command {
-        Commands::Welcome(welcome_req) => welcome(&client, welcome_req)?,
-        Commands::Traffic(traffic_req) => traffic(&client, traffic_req)?,
-        Commands::LiveTrafficSnapshot(traffic_req) => live_traffic_snapshot(&client, traffic_req)?,
-        Commands::AllAvailableCameras(cameras_req) => all_available_cameras(&client, cameras_req)?,
-        Commands::CamerasBySegmentId(cameras_req) => cameras_by_segmant_id(&client, cameras_req)?,
-        Commands::CameraByMacId(cameras_req) => camera_by_mac_id(&client, cameras_req)?,
-        Commands::AllSegments(segments_req) => all_segments(&client, segments_req)?,
-        Commands::SegmentById(segment_req) => segment_by_id(&client, segment_req)?,
+    // flags and environment variables override the profile
+    let api_token = match &args.telraam_token {
+        Some(token) => token.clone(),
+        None => profile
+            .token()?
+            .ok_or("no API token, use -t, TELRAAM_TOKEN or a profile in the configuration file")?,
+    };
+    let base_url = args
+        .base_url
+        .as_deref()
+        .or(profile.base_url.as_deref())
+        .unwrap_or(client::TELRAAM_NET);
+
+    let mut builder = TelraamClient::builder(&api_token)
+        .base_url(base_url)
+        .retry_policy(RetryPolicy::with_max_retries(args.max_retries));
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(timeout);
     }
 
-    Ok(())
+    if let Some(rate_limiter) = rate_limiter(&args)? {
+        builder = builder.rate_limiter(rate_limiter);
+    }
+    let client = builder.build()?;
+
+    let result = run(&client, &args, &profile);
+
+    // keep track of the quota, even if the command failed
+    if let (Some(usage_file), Some(rate_limiter)) = (&args.usage_file, client.rate_limiter()) {
+        rate_limiter.usage().save(usage_file)?;
+    }
+
+    result
 }
@@ -0,0 +1,323 @@
+//! Modal share and active travel metrics over [`Report`]s
+//!
+//! The metrics are computed from the estimates of [`crate::estimate`], leaving out the hours below the minimum uptime.
+//! Every result carries a [`Confidence`] flag, based on how much of the period is usable and how many road users were
+//! actually observed in the usable hours.
+
+use std::{collections::BTreeMap, time::SystemTime};
+
+#[cfg(feature = "clap")]
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    estimate::{Counts, UptimeThreshold},
+    export::Tabular,
+    response::Report,
+    time_range,
+};
+
+/// The minimum fraction of the intervals with an estimate for [`Confidence::High`]
+pub const HIGH_CONFIDENCE_COVERAGE: f32 = 0.9;
+
+/// The minimum number of road users observed in the usable intervals for [`Confidence::High`]
+pub const HIGH_CONFIDENCE_COUNT: f32 = 100.0;
+
+/// Below this fraction of the intervals with an estimate, the confidence is [`Confidence::Low`]
+pub const LOW_CONFIDENCE_COVERAGE: f32 = 0.5;
+
+/// Below this number of road users observed in the usable intervals, the confidence is [`Confidence::Low`]
+pub const LOW_CONFIDENCE_COUNT: f32 = 20.0;
+
+/// How far the metrics can be trusted, from the coverage and the number of road users observed in the usable intervals
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Less than half of the intervals are above the minimum uptime, or fewer than 20 road users were observed in them
+    Low,
+    /// Neither low nor high
+    Medium,
+    /// At least 90% of the intervals are above the minimum uptime, and at least 100 road users were observed in them
+    High,
+}
+
+impl Confidence {
+    fn assess(coverage: f32, observed: f32) -> Self {
+        if coverage < LOW_CONFIDENCE_COVERAGE || observed < LOW_CONFIDENCE_COUNT {
+            Self::Low
+        } else if coverage >= HIGH_CONFIDENCE_COVERAGE && observed >= HIGH_CONFIDENCE_COUNT {
+            Self::High
+        } else {
+            Self::Medium
+        }
+    }
+
+    /// The name of the confidence, e.g. `high`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+/// The periods to compute the metrics for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum Breakdown {
+    /// Every report on its own
+    Hour,
+    /// Every local day, in the time zone of the segment
+    #[default]
+    Day,
+    /// The whole period at once
+    Total,
+}
+
+/// The modal share and active travel metrics of a segment over a period
+///
+/// The shares are fractions between 0 and 1 of all road users, `None` if there is no estimate at all.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModalShare {
+    /// The segment identifier
+    pub segment_id: isize,
+    /// The start of the period, the first report for [`Breakdown::Total`]
+    #[serde(with = "humantime_serde")]
+    pub date: SystemTime,
+    /// The number of reports in the period
+    pub intervals: usize,
+    /// The fraction of the reports with an uptime above the minimum
+    pub coverage: f32,
+    /// The mean uptime of the reports
+    pub uptime: f32,
+    /// The estimated number of heavy vehicles
+    pub heavy: f32,
+    /// The estimated number of cars
+    pub car: f32,
+    /// The estimated number of two-wheelers
+    pub bike: f32,
+    /// The estimated number of pedestrians
+    pub pedestrian: f32,
+    /// The share of heavy vehicles
+    pub heavy_share: Option<f32>,
+    /// The share of cars
+    pub car_share: Option<f32>,
+    /// The share of two-wheelers
+    pub bike_share: Option<f32>,
+    /// The share of pedestrians
+    pub pedestrian_share: Option<f32>,
+    /// The share of two-wheelers and pedestrians together
+    pub active_share: Option<f32>,
+    /// The number of two-wheelers per car, `None` if there are no cars
+    pub bike_car_ratio: Option<f32>,
+    /// How far the metrics can be trusted
+    pub confidence: Confidence,
+}
+
+impl ModalShare {
+    /// The metrics of a group of reports of one segment, `None` if there are no reports
+    pub fn from_reports(reports: &[&Report], threshold: &UptimeThreshold) -> Option<Self> {
+        let first = reports.first()?;
+        let estimates = threshold.estimate(reports.iter().copied());
+        let Counts {
+            heavy,
+            car,
+            bike,
+            pedestrian,
+        } = estimates.total();
+        let total = heavy + car + bike + pedestrian;
+        let share = |count: f32| (total > 0.0).then(|| count / total);
+
+        Some(Self {
+            segment_id: first.segment_id,
+            date: first.date,
+            intervals: reports.len(),
+            coverage: estimates.coverage(),
+            uptime: reports.iter().map(|report| report.uptime).sum::<f32>() / reports.len() as f32,
+            heavy,
+            car,
+            bike,
+            pedestrian,
+            heavy_share: share(heavy),
+            car_share: share(car),
+            bike_share: share(bike),
+            pedestrian_share: share(pedestrian),
+            active_share: share(bike + pedestrian),
+            bike_car_ratio: (car > 0.0).then(|| bike / car),
+            // only the road users observed in the hours that are used, not those in the hours left out
+            confidence: Confidence::assess(estimates.coverage(), estimates.observed().total()),
+        })
+    }
+}
+
+/// The metrics per segment (and instance) and period, ordered by segment and then time
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a report has an unknown time zone, for [`Breakdown::Day`]
+pub fn modal_shares(
+    reports: &[Report],
+    breakdown: Breakdown,
+    threshold: &UptimeThreshold,
+) -> Result<Vec<ModalShare>, Error> {
+    let mut groups = BTreeMap::<_, Vec<&Report>>::new();
+    for report in reports {
+        let start = match breakdown {
+            Breakdown::Hour => Some(report.date),
+            Breakdown::Day => Some(time_range::start_of_day(
+                report.local_date()?,
+                &report.tz()?,
+            )),
+            Breakdown::Total => None,
+        };
+        groups
+            .entry((report.segment_id, report.instance_id, start))
+            .or_default()
+            .push(report);
+    }
+
+    Ok(groups
+        .into_iter()
+        .filter_map(|((_, _, start), group)| {
+            let mut share = ModalShare::from_reports(&group, threshold)?;
+            share.date = start.unwrap_or(share.date);
+            Some(share)
+        })
+        .collect())
+}
+
+impl Tabular for ModalShare {
+    fn columns() -> Vec<String> {
+        [
+            "segment_id",
+            "date",
+            "intervals",
+            "coverage",
+            "uptime",
+            "heavy",
+            "car",
+            "bike",
+            "pedestrian",
+            "heavy_share",
+            "car_share",
+            "bike_share",
+            "pedestrian_share",
+            "active_share",
+            "bike_car_ratio",
+            "confidence",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    fn record(&self) -> Vec<String> {
+        let share =
+            |share: Option<f32>| share.map(|share| format!("{share:.3}")).unwrap_or_default();
+
+        vec![
+            self.segment_id.to_string(),
+            humantime::format_rfc3339_seconds(self.date).to_string(),
+            self.intervals.to_string(),
+            format!("{:.2}", self.coverage),
+            format!("{:.2}", self.uptime),
+            format!("{:.1}", self.heavy),
+            format!("{:.1}", self.car),
+            format!("{:.1}", self.bike),
+            format!("{:.1}", self.pedestrian),
+            share(self.heavy_share),
+            share(self.car_share),
+            share(self.bike_share),
+            share(self.pedestrian_share),
+            share(self.active_share),
+            share(self.bike_car_ratio),
+            self.confidence.as_str().to_string(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(date: &str, uptime: f32, car: f32, bike: f32) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": {uptime}, "heavy": 0, "car": {car}, "bike": {bike}, "pedestrian": 10, "heavy_lft": 0, "heavy_rgt": 0, "car_lft": 0, "car_rgt": {car}, "bike_lft": 0, "bike_rgt": {bike}, "pedestrian_lft": 5, "pedestrian_rgt": 5, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 0}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    #[test]
+    fn test_modal_shares() {
+        let reports = [
+            report("2024-05-01T07:00:00Z", 1.0, 60.0, 30.0),
+            // counted half of the hour, the counts are already extrapolated to the hour
+            report("2024-05-01T08:00:00Z", 0.5, 40.0, 20.0),
+            // below the minimum uptime, left out
+            report("2024-05-01T09:00:00Z", 0.1, 1000.0, 0.0),
+            // the next local day
+            report("2024-05-01T22:00:00Z", 1.0, 4.0, 4.0),
+        ];
+        let threshold = UptimeThreshold::default();
+
+        let days = modal_shares(&reports, Breakdown::Day, &threshold).unwrap();
+        assert_eq!(2, days.len());
+        assert_eq!(
+            humantime::parse_rfc3339("2024-04-30T22:00:00Z").unwrap(),
+            days[0].date
+        );
+
+        let day = &days[0];
+        assert_eq!(3, day.intervals);
+        assert_eq!(100.0, day.car);
+        assert_eq!(50.0, day.bike);
+        assert_eq!(20.0, day.pedestrian);
+        assert_eq!(Some(100.0 / 170.0), day.car_share);
+        assert_eq!(Some(70.0 / 170.0), day.active_share);
+        assert_eq!(Some(0.5), day.bike_car_ratio);
+        assert_eq!(Confidence::Medium, day.confidence);
+
+        // 18 road users, all hours covered
+        assert_eq!(Confidence::Low, days[1].confidence);
+        assert_eq!(Some(1.0), days[1].bike_car_ratio);
+
+        let hours = modal_shares(&reports, Breakdown::Hour, &threshold).unwrap();
+        assert_eq!(4, hours.len());
+        assert_eq!(None, hours[2].car_share);
+        assert_eq!(Confidence::Low, hours[2].confidence);
+
+        let total = modal_shares(&reports, Breakdown::Total, &threshold).unwrap();
+        assert_eq!(1, total.len());
+        assert_eq!(reports[0].date, total[0].date);
+        assert_eq!(104.0, total[0].car);
+        assert_eq!(0.75, total[0].coverage);
+    }
+
+    #[test]
+    fn test_confidence_ignores_unusable_hours() {
+        let reports = [
+            // 15 road users
+            report("2024-05-01T07:00:00Z", 1.0, 5.0, 0.0),
+            // below the minimum uptime, its road users do not count either
+            report("2024-05-01T08:00:00Z", 0.1, 1000.0, 0.0),
+        ];
+
+        let total = modal_shares(&reports, Breakdown::Total, &UptimeThreshold::default()).unwrap();
+        assert_eq!(0.5, total[0].coverage);
+        assert_eq!(5.0, total[0].car);
+        assert_eq!(Confidence::Low, total[0].confidence);
+    }
+
+    #[test]
+    fn test_record() {
+        let reports = [report("2024-05-01T07:00:00Z", 1.0, 60.0, 30.0)];
+        let shares = modal_shares(&reports, Breakdown::Hour, &UptimeThreshold::default()).unwrap();
+        let record = shares[0].record();
+
+        assert_eq!(ModalShare::columns().len(), record.len());
+        assert_eq!("2024-05-01T07:00:00Z", record[1]);
+        assert_eq!("0.600", record[10]);
+        assert_eq!("high", record[15]);
+    }
+}
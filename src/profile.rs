@@ -0,0 +1,416 @@
+//! Typical time-of-day profiles of hourly [`Report`]s, with the morning and evening peaks
+//!
+//! A profile is the mean count in each local hour of the day over all weekdays or all weekend days, leaving out the
+//! hours below the minimum uptime, see [`crate::estimate`]. Public holidays are not known, they count as weekdays.
+
+use std::{collections::BTreeSet, ops::Range};
+
+use chrono::{Datelike, Timelike, Weekday};
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    estimate::UptimeThreshold,
+    export::Tabular,
+    response::{Mode, Report, ReportInterval},
+};
+
+/// The local hours searched for the morning peak, 06:00 to 10:00
+pub const AM_PEAK_HOURS: Range<u32> = 6..10;
+
+/// The local hours searched for the evening peak, 15:00 to 19:00
+pub const PM_PEAK_HOURS: Range<u32> = 15..19;
+
+/// Weekdays or weekend days
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DayType {
+    /// Monday to Friday
+    Weekday,
+    /// Saturday and Sunday
+    Weekend,
+}
+
+impl DayType {
+    /// Both day types
+    pub const ALL: [Self; 2] = [Self::Weekday, Self::Weekend];
+
+    /// The type of the day
+    pub fn of(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Sat | Weekday::Sun => Self::Weekend,
+            _ => Self::Weekday,
+        }
+    }
+
+    /// The name of the day type, e.g. `weekday`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Weekday => "weekday",
+            Self::Weekend => "weekend",
+        }
+    }
+}
+
+/// The morning or the evening
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeakPeriod {
+    /// See [`AM_PEAK_HOURS`]
+    Am,
+    /// See [`PM_PEAK_HOURS`]
+    Pm,
+}
+
+impl PeakPeriod {
+    /// The local hours searched for the peak
+    pub fn hours(self) -> Range<u32> {
+        match self {
+            Self::Am => AM_PEAK_HOURS,
+            Self::Pm => PM_PEAK_HOURS,
+        }
+    }
+
+    /// The name of the period, `am` or `pm`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Am => "am",
+            Self::Pm => "pm",
+        }
+    }
+}
+
+/// The busiest hour of a peak period of a profile
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Peak {
+    /// The type of the days of the profile
+    pub day_type: DayType,
+    /// The mode of the profile
+    pub mode: Mode,
+    /// The morning or the evening
+    pub period: PeakPeriod,
+    /// The local hour, e.g. `8` for 08:00 to 09:00
+    pub hour: u32,
+    /// The mean count in the peak hour
+    pub count: f32,
+    /// The mean count of the hours of the peak period divided by the count of the peak hour, between 0 and 1
+    ///
+    /// The hourly analogue of the usual 15 minute peak hour factor: close to 1 for a long, flat peak, low for a short,
+    /// sharp one.
+    pub peak_hour_factor: f32,
+    /// The share of the traffic of the whole day in the peak hour, `None` if the daily total is not known, see
+    /// [`HourlyProfile::daily_total`], or there is no traffic
+    pub daily_share: Option<f32>,
+}
+
+/// The typical traffic of a mode over the hours of a weekday or a weekend day
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HourlyProfile {
+    /// The type of the days
+    pub day_type: DayType,
+    /// The mode of transport
+    pub mode: Mode,
+    /// The number of days with data
+    pub days: usize,
+    /// The mean count in each local hour, from 0 to 23, `None` if there is no data for that hour
+    pub hours: Vec<Option<f32>>,
+    /// The sum of the mean counts of all hours, `None` if some hours have no data, as their traffic would be missing
+    pub daily_total: Option<f32>,
+    /// The busiest hour of the morning
+    pub am_peak: Option<Peak>,
+    /// The busiest hour of the evening
+    pub pm_peak: Option<Peak>,
+}
+
+impl HourlyProfile {
+    /// The profile of the mode over the days of the type, `None` if there are no such days with data
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if a report is not hourly, or has an unknown time zone
+    pub fn new(
+        reports: &[Report],
+        day_type: DayType,
+        mode: Mode,
+        threshold: &UptimeThreshold,
+    ) -> Result<Option<Self>, Error> {
+        let mut sums = [(0.0_f32, 0_u32); 24];
+        let mut days = BTreeSet::new();
+
+        for report in reports {
+            if report.interval != ReportInterval::Hourly {
+                return Err(Error::InvalidArgument {
+                    name: "interval",
+                    message: format!(
+                        "profiles need hourly reports, got {}",
+                        report.interval.as_str()
+                    ),
+                });
+            }
+
+            let local = report.local_start()?;
+            if DayType::of(local.weekday()) != day_type {
+                continue;
+            }
+            if !threshold.is_usable(report.uptime) {
+                continue;
+            }
+
+            let (sum, count) = &mut sums[local.hour() as usize];
+            *sum += report.count(mode);
+            *count += 1;
+            days.insert(local.date_naive());
+        }
+
+        if days.is_empty() {
+            return Ok(None);
+        }
+
+        let hours = sums
+            .iter()
+            .map(|&(sum, count)| (count > 0).then(|| sum / count as f32))
+            .collect::<Vec<_>>();
+        let mut profile = Self {
+            day_type,
+            mode,
+            days: days.len(),
+            daily_total: hours.iter().copied().sum(),
+            hours,
+            am_peak: None,
+            pm_peak: None,
+        };
+        profile.am_peak = profile.peak(PeakPeriod::Am);
+        profile.pm_peak = profile.peak(PeakPeriod::Pm);
+        Ok(Some(profile))
+    }
+
+    /// The busiest hour of the period, `None` if there is no data for it
+    pub fn peak(&self, period: PeakPeriod) -> Option<Peak> {
+        let counts = period
+            .hours()
+            .filter_map(|hour| Some((hour, self.hours.get(hour as usize).copied().flatten()?)))
+            .collect::<Vec<_>>();
+        let &(hour, count) = counts.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        let mean = counts.iter().map(|(_, count)| count).sum::<f32>() / counts.len() as f32;
+        Some(Peak {
+            day_type: self.day_type,
+            mode: self.mode,
+            period,
+            hour,
+            count,
+            peak_hour_factor: if count > 0.0 { mean / count } else { 0.0 },
+            daily_share: self
+                .daily_total
+                .filter(|&total| total > 0.0)
+                .map(|total| count / total),
+        })
+    }
+}
+
+/// The weekday and weekend profiles of every mode, leaving out day types without data
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a report is not hourly, or has an unknown time zone
+pub fn profiles(
+    reports: &[Report],
+    threshold: &UptimeThreshold,
+) -> Result<Vec<HourlyProfile>, Error> {
+    let mut profiles = Vec::new();
+    for day_type in DayType::ALL {
+        for mode in Mode::ALL {
+            profiles.extend(HourlyProfile::new(reports, day_type, mode, threshold)?);
+        }
+    }
+    Ok(profiles)
+}
+
+/// One local hour of all profiles, for a table with a column per day type and mode
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProfileRow {
+    /// The local hour
+    pub hour: u32,
+    /// The mean counts, in the order of the columns after the hour, see [`Tabular::columns`]
+    pub counts: Vec<Option<f32>>,
+}
+
+impl ProfileRow {
+    /// The rows of the hours 0 to 23 of the profiles
+    pub fn rows(profiles: &[HourlyProfile]) -> Vec<Self> {
+        (0..24)
+            .map(|hour| Self {
+                hour,
+                counts: DayType::ALL
+                    .into_iter()
+                    .flat_map(|day_type| Mode::ALL.map(|mode| (day_type, mode)))
+                    .map(|(day_type, mode)| {
+                        profiles
+                            .iter()
+                            .find(|profile| profile.day_type == day_type && profile.mode == mode)
+                            .and_then(|profile| profile.hours[hour as usize])
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Tabular for ProfileRow {
+    fn columns() -> Vec<String> {
+        let mut columns = vec!["hour".to_string()];
+        for day_type in DayType::ALL {
+            for mode in Mode::ALL {
+                columns.push(format!("{}_{}", day_type.as_str(), mode.as_str()));
+            }
+        }
+        columns
+    }
+
+    fn record(&self) -> Vec<String> {
+        std::iter::once(self.hour.to_string())
+            .chain(
+                self.counts
+                    .iter()
+                    .map(|count| count.map(|count| format!("{count:.1}")).unwrap_or_default()),
+            )
+            .collect()
+    }
+}
+
+impl Tabular for Peak {
+    fn columns() -> Vec<String> {
+        [
+            "day_type",
+            "mode",
+            "period",
+            "hour",
+            "count",
+            "peak_hour_factor",
+            "daily_share",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.day_type.as_str().to_string(),
+            self.mode.as_str().to_string(),
+            self.period.as_str().to_string(),
+            self.hour.to_string(),
+            format!("{:.1}", self.count),
+            format!("{:.2}", self.peak_hour_factor),
+            self.daily_share
+                .map(|share| format!("{share:.3}"))
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(date: &str, uptime: f32, car: f32) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": {uptime}, "heavy": 0, "car": {car}, "bike": 1, "pedestrian": 0, "heavy_lft": 0, "heavy_rgt": 0, "car_lft": 0, "car_rgt": {car}, "bike_lft": 0, "bike_rgt": 1, "pedestrian_lft": 0, "pedestrian_rgt": 0, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 0}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    fn reports() -> Vec<Report> {
+        vec![
+            // Monday 2024-05-06, local hours 7, 8, 9, 12 and 17
+            report("2024-05-06T05:00:00Z", 1.0, 40.0),
+            report("2024-05-06T06:00:00Z", 1.0, 100.0),
+            report("2024-05-06T07:00:00Z", 1.0, 60.0),
+            report("2024-05-06T10:00:00Z", 1.0, 50.0),
+            report("2024-05-06T15:00:00Z", 1.0, 90.0),
+            // Tuesday, counted half of the hour at 08:00, the count is already extrapolated to the hour
+            report("2024-05-07T06:00:00Z", 0.5, 80.0),
+            // below the minimum uptime, left out
+            report("2024-05-07T15:00:00Z", 0.1, 1000.0),
+            // Saturday 11:00
+            report("2024-05-11T09:00:00Z", 1.0, 30.0),
+        ]
+    }
+
+    #[test]
+    fn test_weekday_profile() {
+        let threshold = UptimeThreshold::default();
+        let profile = HourlyProfile::new(&reports(), DayType::Weekday, Mode::Car, &threshold)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(2, profile.days);
+        assert_eq!(24, profile.hours.len());
+        assert_eq!(Some(90.0), profile.hours[8]);
+        assert_eq!(None, profile.hours[10]);
+        // the night hours are missing, so the daily total is not known
+        assert_eq!(None, profile.daily_total);
+
+        let am = profile.am_peak.unwrap();
+        assert_eq!(8, am.hour);
+        assert_eq!(90.0, am.count);
+        // (40 + 90 + 60) / 3 / 90
+        assert_eq!(190.0 / 3.0 / 90.0, am.peak_hour_factor);
+        assert_eq!(None, am.daily_share);
+
+        let pm = profile.pm_peak.unwrap();
+        assert_eq!(17, pm.hour);
+        assert_eq!(1.0, pm.peak_hour_factor);
+    }
+
+    #[test]
+    fn test_daily_share() {
+        // every hour of Monday 2024-05-06, local time, 80 cars at 08:00 and 10 in the other hours
+        let reports = (0..24)
+            .map(|hour_of_day| {
+                let date = humantime::format_rfc3339(
+                    humantime::parse_rfc3339("2024-05-05T22:00:00Z").unwrap()
+                        + std::time::Duration::from_secs(hour_of_day * 3600),
+                )
+                .to_string();
+                report(&date, 1.0, if hour_of_day == 8 { 80.0 } else { 10.0 })
+            })
+            .collect::<Vec<_>>();
+
+        let profile = HourlyProfile::new(
+            &reports,
+            DayType::Weekday,
+            Mode::Car,
+            &UptimeThreshold::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(Some(310.0), profile.daily_total);
+        assert_eq!(Some(80.0 / 310.0), profile.am_peak.unwrap().daily_share);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profiles = profiles(&reports(), &UptimeThreshold::default()).unwrap();
+        assert_eq!(8, profiles.len());
+
+        let weekend = profiles
+            .iter()
+            .find(|profile| profile.day_type == DayType::Weekend && profile.mode == Mode::Car)
+            .unwrap();
+        assert_eq!(1, weekend.days);
+        assert_eq!(None, weekend.am_peak);
+
+        let rows = ProfileRow::rows(&profiles);
+        assert_eq!(24, rows.len());
+        assert_eq!(ProfileRow::columns().len(), rows[8].record().len());
+        assert_eq!(
+            vec!["8", "0.0", "90.0", "1.0", "0.0", "", "", "", ""],
+            rows[8].record()
+        );
+        assert_eq!("30.0", rows[11].record()[6]);
+
+        let mut daily = reports();
+        daily[0].interval = ReportInterval::Daily;
+        assert!(super::profiles(&daily, &UptimeThreshold::default()).is_err());
+    }
+}
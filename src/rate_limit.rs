@@ -0,0 +1,285 @@
+//! Client side rate limiting, to stay within the per second and daily request quotas of a Telraam API token
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What to do when a request would exceed the per second rate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RateLimitMode {
+    /// Wait until the request is allowed
+    #[default]
+    Wait,
+    /// Fail immediately with [`Error::RateLimited`]
+    FailFast,
+}
+
+/// The limits of an API token
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// Number of requests allowed per second, on average, `None` for no limit
+    pub per_second: Option<f64>,
+    /// Number of requests that can be sent at once, before being limited to `per_second`
+    pub burst: u32,
+    /// Maximum number of requests per (UTC) day, `None` for no limit
+    pub daily_quota: Option<u64>,
+    /// What to do when the per second rate is exceeded, the daily quota always fails with [`Error::QuotaExceeded`]
+    pub mode: RateLimitMode,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_second: Some(1.0),
+            burst: 1,
+            daily_quota: None,
+            mode: RateLimitMode::Wait,
+        }
+    }
+}
+
+/// The number of requests made on a given day, this can be persisted to keep track of the quota across runs
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DailyUsage {
+    /// The (UTC) day, as the number of days since the Unix epoch
+    pub day: u64,
+    /// The number of requests made on that day
+    pub requests: u64,
+}
+
+impl DailyUsage {
+    /// Load the usage from a JSON file, returns the default (no requests) if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(io::Error::from),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Save the usage to a JSON file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    last_refill: Instant,
+    usage: DailyUsage,
+}
+
+/// A token bucket rate limiter with a daily quota
+///
+/// Clones share the same state, so a single limiter can be used across clients, clones of clients and threads that
+/// share the same API token.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    /// Construct a new limiter, with a full bucket and no requests made today
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_usage(limit, DailyUsage::default())
+    }
+
+    /// Construct a new limiter, continuing from previously recorded usage, see [`DailyUsage::load`]
+    pub fn with_usage(limit: RateLimit, usage: DailyUsage) -> Self {
+        let state = State {
+            tokens: f64::from(limit.burst.max(1)),
+            last_refill: Instant::now(),
+            usage,
+        };
+
+        Self {
+            limit,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// The limits this was constructed with
+    pub fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+
+    /// The number of requests made today, save this to keep track of the daily quota across runs
+    pub fn usage(&self) -> DailyUsage {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        roll_over(&mut state.usage, today());
+        state.usage.clone()
+    }
+
+    /// Reserve a request, returns how long to wait before sending it
+    ///
+    /// # Errors
+    ///
+    /// [`Error::QuotaExceeded`] if the daily quota has been used up, or [`Error::RateLimited`] in [`RateLimitMode::FailFast`]
+    pub fn acquire(&self) -> Result<Duration, Error> {
+        self.acquire_at(Instant::now(), today())
+    }
+
+    fn acquire_at(&self, now: Instant, day: u64) -> Result<Duration, Error> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        roll_over(&mut state.usage, day);
+        if let Some(quota) = self.limit.daily_quota {
+            if state.usage.requests >= quota {
+                return Err(Error::QuotaExceeded {
+                    quota,
+                    resets_in: Duration::from_secs((day + 1) * SECS_PER_DAY)
+                        .saturating_sub(since_epoch()),
+                });
+            }
+        }
+
+        let wait = match self.limit.per_second {
+            Some(per_second) => {
+                let per_second = per_second.max(f64::MIN_POSITIVE);
+
+                // refill the bucket
+                let elapsed = now.saturating_duration_since(state.last_refill);
+                state.tokens = (state.tokens + elapsed.as_secs_f64() * per_second)
+                    .min(f64::from(self.limit.burst.max(1)));
+                state.last_refill = now;
+
+                // tokens can go negative, which reserves a slot in the future for concurrent callers
+                let wait = if state.tokens >= 1.0 {
+                    Duration::ZERO
+                } else {
+                    Duration::from_secs_f64((1.0 - state.tokens) / per_second)
+                };
+
+                if wait > Duration::ZERO && self.limit.mode == RateLimitMode::FailFast {
+                    return Err(Error::RateLimited { wait });
+                }
+
+                state.tokens -= 1.0;
+                wait
+            }
+            None => Duration::ZERO,
+        };
+
+        state.usage.requests += 1;
+        Ok(wait)
+    }
+}
+
+fn roll_over(usage: &mut DailyUsage, day: u64) {
+    if usage.day != day {
+        *usage = DailyUsage { day, requests: 0 };
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn today() -> u64 {
+    since_epoch().as_secs() / SECS_PER_DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_wait() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: Some(2.0),
+            burst: 2,
+            ..RateLimit::default()
+        });
+        let now = Instant::now();
+
+        assert_eq!(Duration::ZERO, limiter.acquire_at(now, 1).unwrap());
+        assert_eq!(Duration::ZERO, limiter.acquire_at(now, 1).unwrap());
+        assert_eq!(
+            Duration::from_millis(500),
+            limiter.acquire_at(now, 1).unwrap()
+        );
+        // shared across clones, and the previous caller reserved the next slot
+        assert_eq!(
+            Duration::from_millis(1000),
+            limiter.clone().acquire_at(now, 1).unwrap()
+        );
+
+        // after the bucket refills, no more waiting
+        let later = now + Duration::from_secs(2);
+        assert_eq!(Duration::ZERO, limiter.acquire_at(later, 1).unwrap());
+        assert_eq!(5, limiter.state.lock().unwrap().usage.requests);
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let limiter = RateLimiter::new(RateLimit {
+            mode: RateLimitMode::FailFast,
+            ..RateLimit::default()
+        });
+        let now = Instant::now();
+
+        assert_eq!(Duration::ZERO, limiter.acquire_at(now, 1).unwrap());
+        let error = limiter.acquire_at(now, 1).expect_err("should be limited");
+        assert!(matches!(error, Error::RateLimited { wait } if wait == Duration::from_secs(1)));
+        assert!(!error.is_retryable());
+        assert_eq!(1, limiter.state.lock().unwrap().usage.requests);
+    }
+
+    #[test]
+    fn test_daily_quota() {
+        let limiter = RateLimiter::with_usage(
+            RateLimit {
+                per_second: None,
+                daily_quota: Some(3),
+                ..RateLimit::default()
+            },
+            DailyUsage {
+                day: 1,
+                requests: 2,
+            },
+        );
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at(now, 1).is_ok());
+        let error = limiter.acquire_at(now, 1).expect_err("quota used up");
+        assert!(matches!(error, Error::QuotaExceeded { quota: 3, .. }));
+
+        // the next day the quota is available again
+        assert!(limiter.acquire_at(now, 2).is_ok());
+        assert_eq!(
+            DailyUsage {
+                day: 2,
+                requests: 1
+            },
+            limiter.state.lock().unwrap().usage
+        );
+    }
+
+    #[test]
+    fn test_usage_load_save() {
+        let path = std::env::temp_dir().join(format!("telraam-usage-{}.json", std::process::id()));
+        assert_eq!(DailyUsage::default(), DailyUsage::load(&path).unwrap());
+
+        let usage = DailyUsage {
+            day: 19700,
+            requests: 42,
+        };
+        usage.save(&path).unwrap();
+        assert_eq!(usage, DailyUsage::load(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
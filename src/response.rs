@@ -1,14 +1,26 @@
 //! All Response types from the Telraam API
 
-use std::time::SystemTime;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
 
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+use chrono_tz::Tz;
+#[cfg(feature = "clap")]
+use clap::ValueEnum;
 use geojson::GeoJson;
 use serde::{
     de::{self, DeserializeOwned, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::error::Error;
+use crate::{
+    crs::{self, Crs},
+    endpoint::TrafficFormat,
+    error::Error,
+    feature::{self, SegmentProperties, SnapshotProperties, TypedFeature},
+};
 
 /// All Responses have a status and must be Deserializable
 pub trait Response: DeserializeOwned {
@@ -88,7 +100,7 @@ impl TrafficResponse {
 }
 
 /// Report data returned from the [`crate::endpoint::Traffic`] request
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Report {
     /// the instance identifier for "instance" level calls ("-1" for "segment" level calls)
     pub instance_id: isize,
@@ -98,7 +110,7 @@ pub struct Report {
     #[serde(with = "humantime_serde")]
     pub date: SystemTime,
     /// can be "hourly" or "daily" for hourly or daily aggregate data, respectively
-    pub interval: String,
+    pub interval: ReportInterval,
     /// between 0 and 1, represents the portion of the reporting interval (hour or day) that was actively spent counting the traffic (background calculation intervals in hourly periods, and the night time in daily periods contribute to values being less than 1)
     pub uptime: f32,
     /// the number of heavy vehicles (called lorry in older APIs, but all stand for the same: anything larger than car) on this day (and in this hour)
@@ -137,7 +149,190 @@ pub struct Report {
     pub v85: f32,
 }
 
-/// Response from [`crate::endpoint::LiveTrafficSnapshot`]
+/// A mode of transport counted in a [`Report`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Heavy vehicles, anything larger than a car
+    Heavy,
+    /// Cars
+    #[default]
+    Car,
+    /// Two-wheelers, mainly cyclists and motorbikes
+    Bike,
+    /// Pedestrians
+    Pedestrian,
+}
+
+impl Mode {
+    /// All modes, from the heaviest to the lightest
+    pub const ALL: [Self; 4] = [Self::Heavy, Self::Car, Self::Bike, Self::Pedestrian];
+
+    /// The name of the mode, as in the fields of a [`Report`], e.g. `car`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Heavy => "heavy",
+            Self::Car => "car",
+            Self::Bike => "bike",
+            Self::Pedestrian => "pedestrian",
+        }
+    }
+}
+
+impl Report {
+    /// The count of the mode, in both directions
+    pub fn count(&self, mode: Mode) -> f32 {
+        match mode {
+            Mode::Heavy => self.heavy,
+            Mode::Car => self.car,
+            Mode::Bike => self.bike,
+            Mode::Pedestrian => self.pedestrian,
+        }
+    }
+
+    /// The counts of the mode from the left and from the right
+    pub fn directional_count(&self, mode: Mode) -> (f32, f32) {
+        match mode {
+            Mode::Heavy => (self.heavy_lft, self.heavy_rgt),
+            Mode::Car => (self.car_lft, self.car_rgt),
+            Mode::Bike => (self.bike_lft, self.bike_rgt),
+            Mode::Pedestrian => (self.pedestrian_lft, self.pedestrian_rgt),
+        }
+    }
+
+    /// The time zone of the segment, parsed from [`Self::timezone`]
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the time zone is empty or not a known IANA time zone name
+    pub fn tz(&self) -> Result<Tz, Error> {
+        self.timezone
+            .parse::<Tz>()
+            .map_err(|error| Error::InvalidArgument {
+                name: "timezone",
+                message: format!("{:?}: {error}", self.timezone),
+            })
+    }
+
+    /// The start of the reporting interval in the local time of the segment, with the offset that applied at that moment
+    pub fn local_start(&self) -> Result<DateTime<Tz>, Error> {
+        Ok(DateTime::<Utc>::from(self.date).with_timezone(&self.tz()?))
+    }
+
+    /// The local date of the start of the reporting interval, the day the counts belong to
+    pub fn local_date(&self) -> Result<NaiveDate, Error> {
+        Ok(self.local_start()?.date_naive())
+    }
+
+    /// The local hour (0 to 23) of the start of the reporting interval
+    ///
+    /// On the day daylight saving time ends, two consecutive reports have the same local hour, see [`Self::local_start`]
+    /// for the offset that tells them apart.
+    pub fn local_hour(&self) -> Result<u32, Error> {
+        Ok(self.local_start()?.hour())
+    }
+}
+
+/// The aggregation interval of a [`Report`], matching the requested [`crate::endpoint::TrafficFormat`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportInterval {
+    /// hourly aggregate data, for [`crate::endpoint::TrafficFormat::PerHour`]
+    Hourly,
+    /// daily aggregate data, for [`crate::endpoint::TrafficFormat::PerDay`]
+    Daily,
+}
+
+impl ReportInterval {
+    /// The nominal length of the interval, ignoring daylight saving time
+    pub fn duration(self) -> Duration {
+        match self {
+            Self::Hourly => Duration::from_secs(60 * 60),
+            Self::Daily => Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// The name of the interval, as in the API, e.g. `hourly`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+        }
+    }
+}
+
+impl From<TrafficFormat> for ReportInterval {
+    fn from(format: TrafficFormat) -> Self {
+        match format {
+            TrafficFormat::PerHour => Self::Hourly,
+            TrafficFormat::PerDay => Self::Daily,
+        }
+    }
+}
+
+/// The traffic of several segments (or instances), keyed by their identifier, see [`crate::client::TelraamClient::traffic_many`]
+///
+/// Each identifier either succeeded with its reports, or failed with its error, so that one bad identifier does not
+/// lose the traffic of all the others.
+#[derive(Debug, Default)]
+pub struct MultiTraffic {
+    /// The reports of each identifier that succeeded
+    pub reports: BTreeMap<String, Vec<Report>>,
+    /// The error of each identifier that failed
+    pub failures: BTreeMap<String, Error>,
+}
+
+impl MultiTraffic {
+    /// Record the result for the identifier
+    pub fn insert(&mut self, id: impl Into<String>, result: Result<Vec<Report>, Error>) {
+        let id = id.into();
+        match result {
+            Ok(reports) => {
+                self.failures.remove(&id);
+                self.reports.insert(id, reports);
+            }
+            Err(error) => {
+                self.reports.remove(&id);
+                self.failures.insert(id, error);
+            }
+        }
+    }
+
+    /// The identifiers that succeeded
+    pub fn succeeded(&self) -> impl Iterator<Item = &str> + '_ {
+        self.reports.keys().map(String::as_str)
+    }
+
+    /// The identifiers that failed
+    pub fn failed(&self) -> impl Iterator<Item = &str> + '_ {
+        self.failures.keys().map(String::as_str)
+    }
+
+    /// True if no identifier failed
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// All reports of the identifiers that succeeded, in one list, see [`merge_reports`]
+    pub fn merged_reports(self) -> Vec<Report> {
+        merge_reports(self.reports.into_values().flatten())
+    }
+}
+
+/// Combine reports from several responses (e.g. for [`crate::endpoint::TrafficRequest::windows`]) into one list, sorted by `date`
+///
+/// Reports with the same `date`, `segment_id` and `instance_id` are duplicates, only the first is kept.
+pub fn merge_reports(reports: impl IntoIterator<Item = Report>) -> Vec<Report> {
+    let mut reports = reports.into_iter().collect::<Vec<_>>();
+
+    // the sort is stable, so the first of the duplicates is the one kept
+    reports.sort_by_key(|report| (report.date, report.segment_id, report.instance_id));
+    reports.dedup_by_key(|report| (report.date, report.segment_id, report.instance_id));
+    reports
+}
+
+/// Response from [`crate::endpoint::TrafficSnapshot`] and [`crate::endpoint::LiveTrafficSnapshot`]
 #[derive(Deserialize)]
 pub struct TrafficSnapshotResponse {
     #[serde(flatten)]
@@ -164,6 +359,14 @@ impl TrafficSnapshotResponse {
         self.status.try_into_error()?;
         Ok(self.geo)
     }
+
+    /// Iterate over the features of the snapshot, with typed properties
+    pub fn snapshot_features(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<TypedFeature<SnapshotProperties>, Error>> + '_, Error>
+    {
+        Ok(feature::typed_features(self.snapshot()?))
+    }
 }
 
 /// Response from [`crate::endpoint::AllAvailableCameras`], [`crate::endpoint::CamerasBySegementId`], and [`crate::endpoint::CameraByMacId`]
@@ -277,7 +480,7 @@ impl CamerasResponse {
     }
 }
 
-/// Response from [`crate::endpoint::AllSegments`] and [`crate::endpoint::SegmentById`]
+/// Response from [`crate::endpoint::AllSegments`], [`crate::endpoint::ActiveSegments`], [`crate::endpoint::ActiveSegmentsByArea`], [`crate::endpoint::SegmentsByArea`] and [`crate::endpoint::SegmentById`]
 #[derive(Deserialize)]
 pub struct SegmentResponse {
     #[serde(flatten)]
@@ -304,6 +507,21 @@ impl SegmentResponse {
         self.status.try_into_error()?;
         Ok(self.segment)
     }
+
+    /// Iterate over the features of the segments, with typed properties
+    pub fn segment_features(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<TypedFeature<SegmentProperties>, Error>> + '_, Error>
+    {
+        Ok(feature::typed_features(self.segments()?))
+    }
+
+    /// Reproject the geometry of the segments, in place
+    ///
+    /// [`crate::endpoint::AllSegments`] returns [`Crs::Lambert72`], the other segment endpoints return [`Crs::Wgs84`].
+    pub fn reproject(&mut self, from: Crs, to: Crs) {
+        crs::reproject(&mut self.segment, from, to);
+    }
 }
 
 #[cfg(test)]
@@ -467,12 +685,73 @@ mod tests {
         assert_eq!("ok", traffic.status.message);
         assert_eq!(2, traffic.reports.len());
         assert_eq!(-1, traffic.reports[0].instance_id);
+        assert_eq!(ReportInterval::Hourly, traffic.reports[0].interval);
         assert_eq!(
             SystemTime::UNIX_EPOCH + Duration::from_secs(1604041200),
             traffic.reports[0].date
         )
     }
 
+    #[test]
+    fn test_merge_reports() {
+        let json = r#"
+          [
+            {"instance_id": -1, "segment_id": 348917, "date": "2020-10-30T08:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5},
+            {"instance_id": -1, "segment_id": 348917, "date": "2020-10-30T07:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5},
+            {"instance_id": -1, "segment_id": 348917, "date": "2020-10-30T08:00:00.000Z", "interval": "hourly", "uptime": 0.9, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5}
+          ]
+        "#;
+
+        let reports = serde_json::from_str::<Vec<Report>>(json).expect("failed to parse json");
+        let merged = merge_reports(reports);
+
+        assert_eq!(2, merged.len());
+        assert_eq!(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1604041200),
+            merged[0].date
+        );
+        assert_eq!(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1604044800),
+            merged[1].date
+        );
+        assert_eq!(0.5, merged[1].uptime);
+    }
+
+    #[test]
+    fn test_local_time() {
+        let json = r#"
+          [
+            {"instance_id": -1, "segment_id": 348917, "date": "2023-10-29T00:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5},
+            {"instance_id": -1, "segment_id": 348917, "date": "2023-10-29T01:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5},
+            {"instance_id": -1, "segment_id": 348917, "date": "2023-06-01T06:00:00.000Z", "interval": "hourly", "uptime": 0.5, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5}
+          ]
+        "#;
+        let reports = serde_json::from_str::<Vec<Report>>(json).expect("failed to parse json");
+
+        // summer time ends at 03:00 local, so 02:00 local happens twice
+        assert_eq!(
+            "2023-10-29T02:00:00+02:00",
+            reports[0].local_start().unwrap().to_rfc3339()
+        );
+        assert_eq!(
+            "2023-10-29T02:00:00+01:00",
+            reports[1].local_start().unwrap().to_rfc3339()
+        );
+        assert_eq!(2, reports[1].local_hour().unwrap());
+        assert_eq!(
+            NaiveDate::from_ymd_opt(2023, 10, 29).unwrap(),
+            reports[1].local_date().unwrap()
+        );
+
+        assert!(matches!(
+            reports[2].local_hour(),
+            Err(Error::InvalidArgument {
+                name: "timezone",
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_deserialize_traffic_snapshot() {
         let mut json = String::new();
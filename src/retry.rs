@@ -0,0 +1,269 @@
+//! Retry policy for requests to the Telraam API, with exponential backoff, jitter and `Retry-After` support
+
+use std::time::{Duration, SystemTime};
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+
+use crate::error::Error;
+
+/// Defines if and when a failed request is sent again
+///
+/// Only errors that are expected to be temporary are retried, see [`Error::is_retryable`]. Requests to endpoints that are
+/// not idempotent (see [`crate::endpoint::Endpoint::IDEMPOTENT`]) are only retried when the request was certainly not
+/// processed by the server, i.e. the connection could not be established, or the server responded with HTTP 429 or 503.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt, `0` disables retries
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between two attempts, before jitter is applied
+    pub max_backoff: Duration,
+    /// Factor by which the delay grows after each retry
+    pub multiplier: f64,
+    /// Fraction between 0 and 1 of the delay that is randomized, so that many clients don't retry in lockstep
+    pub jitter: f64,
+    /// Wait for the duration in the `Retry-After` response header instead of the backoff, if it is present
+    pub respect_retry_after: bool,
+    /// Give up instead of waiting when the server asks to wait longer than this in `Retry-After`
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            respect_retry_after: true,
+            max_retry_after: Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The default policy with a different number of retries
+    pub fn with_max_retries(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// The backoff before the given retry (starting at 1), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+
+        if backoff.is_finite() {
+            Duration::from_secs_f64(backoff.max(0.0)).min(self.max_backoff)
+        } else {
+            self.max_backoff
+        }
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * fastrand::f64())
+    }
+
+    /// The delay to wait before sending the request again, `None` if the error should not be retried
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of the attempt that failed, starting at 1
+    /// * `error` - The error from the attempt
+    /// * `idempotent` - If the endpoint can safely be sent more than once
+    pub fn retry_delay(&self, attempt: u32, error: &Error, idempotent: bool) -> Option<Duration> {
+        if attempt > self.max_retries || !error.is_retryable() {
+            return None;
+        }
+
+        if !idempotent && !is_safe_to_resend(error) {
+            return None;
+        }
+
+        match error.retry_after() {
+            Some(retry_after) if self.respect_retry_after => {
+                (retry_after <= self.max_retry_after).then_some(retry_after)
+            }
+            _ => Some(self.jittered(self.backoff(attempt))),
+        }
+    }
+}
+
+/// Failures where the server certainly did not process the request
+fn is_safe_to_resend(error: &Error) -> bool {
+    match error {
+        Error::Transport { source, .. } => source.is_connect(),
+        Error::HttpStatus { status, .. } => {
+            *status == StatusCode::TOO_MANY_REQUESTS || *status == StatusCode::SERVICE_UNAVAILABLE
+        }
+        _ => false,
+    }
+}
+
+/// Record of a single attempt to send a request
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    /// The number of the attempt, starting at 1
+    pub number: u32,
+    /// Time taken by the attempt
+    pub elapsed: Duration,
+    /// The HTTP status of the failure, if there was a response
+    pub status: Option<StatusCode>,
+    /// The error message if the attempt failed
+    pub error: Option<String>,
+    /// The delay before the next attempt, `None` if this was the last attempt
+    pub delay: Option<Duration>,
+}
+
+impl Attempt {
+    pub(crate) fn new<T>(
+        number: u32,
+        elapsed: Duration,
+        result: &Result<T, Error>,
+        delay: Option<Duration>,
+    ) -> Self {
+        let (status, error) = match result {
+            Ok(_) => (None, None),
+            Err(error) => (error.status(), Some(error.to_string())),
+        };
+
+        Self {
+            number,
+            elapsed,
+            status,
+            error,
+            delay,
+        }
+    }
+
+    /// True if the attempt succeeded
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The final result of a request, along with the record of all attempts made
+#[derive(Debug)]
+pub struct Attempted<T> {
+    /// The result of the last attempt
+    pub result: Result<T, Error>,
+    /// All attempts, in order
+    pub attempts: Vec<Attempt>,
+}
+
+/// Parse the `Retry-After` header, either a number of seconds or an HTTP date
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn http_status(status: StatusCode, retry_after: Option<Duration>) -> Error {
+        Error::HttpStatus {
+            path: "reports/traffic".to_string(),
+            status,
+            body: String::new(),
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(Duration::from_secs(1), policy.backoff(1));
+        assert_eq!(Duration::from_secs(2), policy.backoff(2));
+        assert_eq!(Duration::from_secs(4), policy.backoff(3));
+        assert_eq!(Duration::from_secs(5), policy.backoff(4));
+        assert_eq!(Duration::from_secs(5), policy.backoff(u32::MAX));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(2),
+            ..RetryPolicy::default()
+        };
+        let unavailable = http_status(StatusCode::SERVICE_UNAVAILABLE, None);
+
+        let delay = policy
+            .retry_delay(1, &unavailable, true)
+            .expect("should retry");
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        assert!(policy.retry_delay(4, &unavailable, true).is_none());
+
+        let not_found = http_status(StatusCode::NOT_FOUND, None);
+        assert!(policy.retry_delay(1, &not_found, true).is_none());
+
+        let internal = http_status(StatusCode::INTERNAL_SERVER_ERROR, None);
+        assert!(policy.retry_delay(1, &internal, true).is_some());
+        assert!(policy.retry_delay(1, &internal, false).is_none());
+        assert!(policy.retry_delay(1, &unavailable, false).is_some());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let policy = RetryPolicy::default();
+
+        let throttled = http_status(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(7)));
+        assert_eq!(
+            Some(Duration::from_secs(7)),
+            policy.retry_delay(1, &throttled, true)
+        );
+
+        let throttled = http_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(600)),
+        );
+        assert!(policy.retry_delay(1, &throttled, true).is_none());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, parse_retry_after(&headers));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(Some(Duration::from_secs(120)), parse_retry_after(&headers));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(Some(Duration::ZERO), parse_retry_after(&headers));
+    }
+}
@@ -0,0 +1,221 @@
+//! Car speed distributions from the speed histograms of [`Report`]s
+//!
+//! The histograms of several reports are merged, weighted by their number of cars, after which percentiles are
+//! interpolated linearly inside the bins. The last bin is open ended (70+ or 120+ km/h), it is assumed to be as wide as
+//! the other bins, so percentiles that fall in it are only a rough estimate.
+
+use serde::Serialize;
+
+use crate::response::Report;
+
+/// Which of the speed histograms of a report to use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedHistogram {
+    /// [`Report::car_speed_hist_0to70plus`], 10 km/h bins
+    #[default]
+    Coarse,
+    /// [`Report::car_speed_hist_0to120plus`], 5 km/h bins
+    Fine,
+}
+
+impl SpeedHistogram {
+    /// The width of the bins in km/h
+    pub fn bin_width(self) -> f64 {
+        match self {
+            Self::Coarse => 10.0,
+            Self::Fine => 5.0,
+        }
+    }
+
+    /// The histogram of the report, in percent of the cars per bin
+    pub fn of(self, report: &Report) -> &[f32] {
+        match self {
+            Self::Coarse => &report.car_speed_hist_0to70plus,
+            Self::Fine => &report.car_speed_hist_0to120plus,
+        }
+    }
+}
+
+/// The distribution of car speeds over a group of reports
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpeedDistribution {
+    /// The histogram the distribution is made from
+    pub histogram: SpeedHistogram,
+    /// The fraction of the cars in each bin, adding up to 1
+    pub shares: Vec<f64>,
+    /// The number of cars in the reports that have a histogram
+    pub cars: f64,
+    /// The `v85` of the server, the mean of the `v85` of the reports weighted by their number of cars, if any has one
+    pub server_v85: Option<f64>,
+}
+
+impl SpeedDistribution {
+    /// Merge the histograms of the reports, weighted by their number of cars
+    ///
+    /// Reports without cars or without the histogram are left out. `None` if no report is left.
+    pub fn from_reports<'a>(
+        reports: impl IntoIterator<Item = &'a Report>,
+        histogram: SpeedHistogram,
+    ) -> Option<Self> {
+        let mut sums = Vec::<f64>::new();
+        let mut cars = 0.0;
+        let (mut v85, mut v85_cars) = (0.0, 0.0);
+
+        for report in reports {
+            let bins = histogram.of(report);
+            let weight = f64::from(report.car);
+            let percent = bins.iter().map(|&share| f64::from(share)).sum::<f64>();
+            if weight <= 0.0 || percent <= 0.0 {
+                continue;
+            }
+
+            if sums.len() < bins.len() {
+                sums.resize(bins.len(), 0.0);
+            }
+            // normalized, as the percentages do not always add up to exactly 100
+            for (sum, &share) in sums.iter_mut().zip(bins) {
+                *sum += f64::from(share) / percent * weight;
+            }
+            cars += weight;
+
+            if report.v85 > 0.0 {
+                v85 += f64::from(report.v85) * weight;
+                v85_cars += weight;
+            }
+        }
+
+        if cars <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            histogram,
+            shares: sums.into_iter().map(|sum| sum / cars).collect(),
+            cars,
+            server_v85: (v85_cars > 0.0).then(|| v85 / v85_cars),
+        })
+    }
+
+    /// The speed in km/h below which the percentage of the cars drive, e.g. 85 for v85
+    ///
+    /// The percentage is clamped to 0 to 100.
+    pub fn percentile(&self, percentage: f64) -> f64 {
+        let width = self.histogram.bin_width();
+        let target = percentage.clamp(0.0, 100.0) / 100.0;
+        let mut below = 0.0;
+
+        for (bin, &share) in self.shares.iter().enumerate() {
+            if share > 0.0 && below + share >= target {
+                return (bin as f64 + (target - below) / share) * width;
+            }
+            below += share;
+        }
+        self.shares.len() as f64 * width
+    }
+
+    /// The median speed in km/h
+    pub fn v50(&self) -> f64 {
+        self.percentile(50.0)
+    }
+
+    /// The speed in km/h that 85% of the cars do not exceed
+    pub fn v85(&self) -> f64 {
+        self.percentile(85.0)
+    }
+
+    /// The speed in km/h that 95% of the cars do not exceed
+    pub fn v95(&self) -> f64 {
+        self.percentile(95.0)
+    }
+
+    /// The mean speed in km/h, taking the middle of each bin
+    pub fn mean(&self) -> f64 {
+        let width = self.histogram.bin_width();
+        self.shares
+            .iter()
+            .enumerate()
+            .map(|(bin, share)| (bin as f64 + 0.5) * width * share)
+            .sum()
+    }
+
+    /// The fraction of the cars faster than the speed limit in km/h, between 0 and 1
+    pub fn share_above(&self, limit: f64) -> f64 {
+        let width = self.histogram.bin_width();
+        self.shares
+            .iter()
+            .enumerate()
+            .map(|(bin, share)| {
+                let from = bin as f64 * width;
+                share * ((from + width - limit) / width).clamp(0.0, 1.0)
+            })
+            .sum()
+    }
+
+    /// How much the v85 of the histogram is above the `v85` of the server, in km/h
+    pub fn v85_deviation(&self) -> Option<f64> {
+        self.server_v85.map(|server| self.v85() - server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(car: f32, hist: &[f32], v85: f32) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "2024-05-01T07:00:00Z", "interval": "hourly", "uptime": 1, "heavy": 0, "car": {car}, "bike": 0, "pedestrian": 0, "heavy_lft": 0, "heavy_rgt": 0, "car_lft": 0, "car_rgt": {car}, "bike_lft": 0, "bike_rgt": 0, "pedestrian_lft": 0, "pedestrian_rgt": 0, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": {hist:?}, "car_speed_hist_0to120plus": [], "v85": {v85}}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let reports = [
+            report(30.0, &[0.0, 0.0, 100.0, 0.0, 0.0, 0.0, 0.0, 0.0], 28.0),
+            report(10.0, &[0.0, 0.0, 0.0, 0.0, 100.0, 0.0, 0.0, 0.0], 48.0),
+            // no cars, left out
+            report(0.0, &[100.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], 0.0),
+        ];
+
+        let speeds = SpeedDistribution::from_reports(&reports, SpeedHistogram::Coarse).unwrap();
+        assert_eq!(40.0, speeds.cars);
+        assert_eq!(
+            vec![0.0, 0.0, 0.75, 0.0, 0.25, 0.0, 0.0, 0.0],
+            speeds.shares
+        );
+
+        // 75% between 20 and 30, 25% between 40 and 50
+        assert_close(20.0 + 10.0 * 0.5 / 0.75, speeds.v50());
+        assert_close(44.0, speeds.v85());
+        assert_close(48.0, speeds.v95());
+        assert_close(0.75 * 25.0 + 0.25 * 45.0, speeds.mean());
+        assert_close(0.25, speeds.share_above(30.0));
+        assert_close(0.125, speeds.share_above(45.0));
+        assert_close(1.0, speeds.share_above(0.0));
+
+        assert_close(33.0, speeds.server_v85.unwrap());
+        assert_close(11.0, speeds.v85_deviation().unwrap());
+    }
+
+    #[test]
+    fn test_unnormalized() {
+        // percentages adding up to 50 are scaled up
+        let reports = [report(10.0, &[25.0, 25.0], 0.0)];
+        let speeds = SpeedDistribution::from_reports(&reports, SpeedHistogram::Coarse).unwrap();
+        assert_eq!(vec![0.5, 0.5], speeds.shares);
+        assert_close(10.0, speeds.v50());
+        assert_eq!(None, speeds.v85_deviation());
+
+        assert_eq!(
+            None,
+            SpeedDistribution::from_reports(&reports, SpeedHistogram::Fine)
+        );
+    }
+}
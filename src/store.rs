@@ -0,0 +1,564 @@
+//! Local SQLite archive of traffic reports, cameras and segments, with incremental sync of the traffic data
+//!
+//! All writes are upserts keyed on the natural identifiers of the rows, so syncing the same data twice is safe. Each
+//! sync fetches the last [`SYNC_LOOKBACK`] again, for the data that cameras uploaded late.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::{
+    client::TelraamClient,
+    endpoint::{TrafficFormat, TrafficLevel, TrafficRequest},
+    error::Error,
+    feature::{SegmentProperties, TypedFeature},
+    response::{Camera, Report, ReportInterval},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS reports (
+    instance_id INTEGER NOT NULL,
+    segment_id INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    interval TEXT NOT NULL,
+    uptime REAL NOT NULL,
+    heavy REAL NOT NULL,
+    car REAL NOT NULL,
+    bike REAL NOT NULL,
+    pedestrian REAL NOT NULL,
+    heavy_lft REAL NOT NULL,
+    heavy_rgt REAL NOT NULL,
+    car_lft REAL NOT NULL,
+    car_rgt REAL NOT NULL,
+    bike_lft REAL NOT NULL,
+    bike_rgt REAL NOT NULL,
+    pedestrian_lft REAL NOT NULL,
+    pedestrian_rgt REAL NOT NULL,
+    direction INTEGER NOT NULL,
+    timezone TEXT NOT NULL,
+    v85 REAL NOT NULL,
+    car_speed_hist_0to70plus TEXT NOT NULL,
+    car_speed_hist_0to120plus TEXT NOT NULL,
+    PRIMARY KEY (segment_id, instance_id, interval, date)
+);
+
+CREATE TABLE IF NOT EXISTS cameras (
+    instance_id INTEGER PRIMARY KEY,
+    mac INTEGER NOT NULL,
+    segment_id INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    time_added TEXT NOT NULL,
+    time_end TEXT,
+    first_data_package TEXT NOT NULL,
+    last_data_package TEXT NOT NULL,
+    camera TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS segments (
+    segment_id INTEGER PRIMARY KEY,
+    properties TEXT NOT NULL,
+    geometry TEXT
+);
+
+CREATE TABLE IF NOT EXISTS sync_state (
+    segment_id INTEGER PRIMARY KEY,
+    synced_until TEXT NOT NULL
+);
+";
+
+const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// How far before the end of the previous sync the next one starts
+///
+/// Cameras upload their data hours or days late, so an hour that was empty at one sync can have data at the next.
+pub const SYNC_LOOKBACK: Duration = Duration::from_secs(48 * 60 * 60);
+
+/// A SQLite database with the reports, cameras and segments, and the sync state of each segment
+///
+/// Timestamps are stored as RFC 3339 text in UTC, e.g. `2023-01-01T08:00:00Z`, which sorts chronologically and works
+/// with the SQLite date functions. Speed histograms are stored as JSON arrays.
+pub struct Store {
+    connection: Connection,
+}
+
+impl Store {
+    /// Open the database at the path, creating it and the tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_connection(Connection::open(path).map_err(Error::Sqlite)?)
+    }
+
+    /// Open a new database in memory, mostly for tests
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::with_connection(Connection::open_in_memory().map_err(Error::Sqlite)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, Error> {
+        connection.execute_batch(SCHEMA).map_err(Error::Sqlite)?;
+        Ok(Self { connection })
+    }
+
+    /// Insert or update the reports, returns the number of rows written
+    pub fn upsert_reports<'a>(
+        &mut self,
+        reports: impl IntoIterator<Item = &'a Report>,
+    ) -> Result<usize, Error> {
+        let transaction = self.connection.transaction().map_err(Error::Sqlite)?;
+        let mut count = 0;
+
+        {
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT INTO reports VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)
+                     ON CONFLICT (segment_id, instance_id, interval, date) DO UPDATE SET
+                        uptime = excluded.uptime, heavy = excluded.heavy, car = excluded.car, bike = excluded.bike,
+                        pedestrian = excluded.pedestrian, heavy_lft = excluded.heavy_lft, heavy_rgt = excluded.heavy_rgt,
+                        car_lft = excluded.car_lft, car_rgt = excluded.car_rgt, bike_lft = excluded.bike_lft,
+                        bike_rgt = excluded.bike_rgt, pedestrian_lft = excluded.pedestrian_lft,
+                        pedestrian_rgt = excluded.pedestrian_rgt, direction = excluded.direction,
+                        timezone = excluded.timezone, v85 = excluded.v85,
+                        car_speed_hist_0to70plus = excluded.car_speed_hist_0to70plus,
+                        car_speed_hist_0to120plus = excluded.car_speed_hist_0to120plus",
+                )
+                .map_err(Error::Sqlite)?;
+
+            for report in reports {
+                count += statement
+                    .execute(params![
+                        report.instance_id as i64,
+                        report.segment_id as i64,
+                        format_time(report.date),
+                        report.interval.as_str(),
+                        report.uptime,
+                        report.heavy,
+                        report.car,
+                        report.bike,
+                        report.pedestrian,
+                        report.heavy_lft,
+                        report.heavy_rgt,
+                        report.car_lft,
+                        report.car_rgt,
+                        report.bike_lft,
+                        report.bike_rgt,
+                        report.pedestrian_lft,
+                        report.pedestrian_rgt,
+                        report.direction as i64,
+                        report.timezone,
+                        report.v85,
+                        to_json(&report.car_speed_hist_0to70plus)?,
+                        to_json(&report.car_speed_hist_0to120plus)?,
+                    ])
+                    .map_err(Error::Sqlite)?;
+            }
+        }
+
+        transaction.commit().map_err(Error::Sqlite)?;
+        Ok(count)
+    }
+
+    /// The reports of a segment with a `date` in `[start, end)`, ordered by date
+    pub fn reports(
+        &self,
+        segment_id: isize,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<Report>, Error> {
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT * FROM reports WHERE segment_id = ?1 AND date >= ?2 AND date < ?3 ORDER BY date, instance_id",
+            )
+            .map_err(Error::Sqlite)?;
+
+        let rows = statement
+            .query_map(
+                params![segment_id as i64, format_time(start), format_time(end)],
+                report_from_row,
+            )
+            .map_err(Error::Sqlite)?;
+
+        rows.collect::<Result<_, _>>().map_err(Error::Sqlite)
+    }
+
+    /// Insert or update the cameras, returns the number of rows written
+    pub fn upsert_cameras<'a>(
+        &mut self,
+        cameras: impl IntoIterator<Item = &'a Camera>,
+    ) -> Result<usize, Error> {
+        let transaction = self.connection.transaction().map_err(Error::Sqlite)?;
+        let mut count = 0;
+
+        {
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT INTO cameras VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                     ON CONFLICT (instance_id) DO UPDATE SET
+                        mac = excluded.mac, segment_id = excluded.segment_id, status = excluded.status,
+                        time_added = excluded.time_added, time_end = excluded.time_end,
+                        first_data_package = excluded.first_data_package,
+                        last_data_package = excluded.last_data_package, camera = excluded.camera",
+                )
+                .map_err(Error::Sqlite)?;
+
+            for camera in cameras {
+                count += statement
+                    .execute(params![
+                        camera.instance_id as i64,
+                        camera.mac as i64,
+                        camera.segment_id as i64,
+                        camera.status,
+                        format_time(camera.time_added),
+                        camera.time_end.map(format_time),
+                        format_time(camera.first_data_package),
+                        format_time(camera.last_data_package),
+                        to_json(camera)?,
+                    ])
+                    .map_err(Error::Sqlite)?;
+            }
+        }
+
+        transaction.commit().map_err(Error::Sqlite)?;
+        Ok(count)
+    }
+
+    /// Insert or update the segments, keyed by their `oidn`, returns the number of rows written
+    pub fn upsert_segments<'a>(
+        &mut self,
+        segments: impl IntoIterator<Item = &'a TypedFeature<SegmentProperties>>,
+    ) -> Result<usize, Error> {
+        let transaction = self.connection.transaction().map_err(Error::Sqlite)?;
+        let mut count = 0;
+
+        {
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT INTO segments VALUES (?1, ?2, ?3)
+                     ON CONFLICT (segment_id) DO UPDATE SET
+                        properties = excluded.properties, geometry = excluded.geometry",
+                )
+                .map_err(Error::Sqlite)?;
+
+            for segment in segments {
+                let geometry = segment.geometry.as_ref().map(to_json).transpose()?;
+                count += statement
+                    .execute(params![
+                        segment.properties.oidn as i64,
+                        to_json(&segment.properties)?,
+                        geometry,
+                    ])
+                    .map_err(Error::Sqlite)?;
+            }
+        }
+
+        transaction.commit().map_err(Error::Sqlite)?;
+        Ok(count)
+    }
+
+    /// The end of the traffic synced for the segment, `None` if it was never synced
+    pub fn last_synced(&self, segment_id: isize) -> Result<Option<SystemTime>, Error> {
+        let synced_until = self
+            .connection
+            .query_row(
+                "SELECT synced_until FROM sync_state WHERE segment_id = ?1",
+                params![segment_id as i64],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(Error::Sqlite)?;
+
+        synced_until
+            .map(|synced_until| parse_time(&synced_until).map_err(Error::Sqlite))
+            .transpose()
+    }
+
+    /// Record the end of the traffic synced for the segment
+    pub fn set_last_synced(&self, segment_id: isize, until: SystemTime) -> Result<(), Error> {
+        self.connection
+            .execute(
+                "INSERT INTO sync_state VALUES (?1, ?2)
+                 ON CONFLICT (segment_id) DO UPDATE SET synced_until = excluded.synced_until",
+                params![segment_id as i64, format_time(until)],
+            )
+            .map_err(Error::Sqlite)?;
+        Ok(())
+    }
+
+    /// Fetch the hourly traffic of the segment that is not in the store yet, up to `until`, and upsert it
+    ///
+    /// Syncing starts [`SYNC_LOOKBACK`] before the end of the previous sync, or at `since` for a segment that was never
+    /// synced. The hours in the lookback are fetched again, so data uploaded late is picked up, and upserted over the
+    /// rows already stored. Returns the number of reports written, including those fetched again.
+    pub fn sync_traffic(
+        &mut self,
+        client: &TelraamClient,
+        segment_id: isize,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> Result<usize, Error> {
+        let time_start = match self.last_synced(segment_id)? {
+            Some(synced_until) => synced_until
+                .checked_sub(SYNC_LOOKBACK)
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            None => since,
+        };
+        if time_start >= until {
+            return Ok(0);
+        }
+
+        let request = TrafficRequest {
+            level: TrafficLevel::Segments,
+            format: TrafficFormat::PerHour,
+            id: segment_id.to_string(),
+            time_start,
+            time_end: until,
+        };
+        let reports = client.traffic(&request)?;
+        let count = self.upsert_reports(&reports)?;
+
+        self.set_last_synced(segment_id, until)?;
+
+        Ok(count)
+    }
+}
+
+/// The start of the hour of the time, e.g. to sync only complete hours
+pub fn truncate_to_hour(time: SystemTime) -> SystemTime {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    SystemTime::UNIX_EPOCH
+        + Duration::from_secs(since_epoch.as_secs() - since_epoch.as_secs() % ONE_HOUR.as_secs())
+}
+
+fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn parse_time(time: &str) -> Result<SystemTime, rusqlite::Error> {
+    humantime::parse_rfc3339(time).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(error))
+    })
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    serde_json::to_string(value)
+        .map_err(|error| Error::Sqlite(rusqlite::Error::ToSqlConversionFailure(Box::new(error))))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(
+    row: &Row<'_>,
+    column: &str,
+) -> Result<T, rusqlite::Error> {
+    let json = row.get::<_, String>(column)?;
+    serde_json::from_str(&json).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(error))
+    })
+}
+
+fn report_from_row(row: &Row<'_>) -> Result<Report, rusqlite::Error> {
+    let interval = match row.get::<_, String>("interval")?.as_str() {
+        "hourly" => ReportInterval::Hourly,
+        "daily" => ReportInterval::Daily,
+        other => {
+            return Err(rusqlite::Error::FromSqlConversionFailure(
+                0,
+                rusqlite::types::Type::Text,
+                format!("unknown interval {other:?}").into(),
+            ))
+        }
+    };
+
+    Ok(Report {
+        instance_id: row.get::<_, i64>("instance_id")? as isize,
+        segment_id: row.get::<_, i64>("segment_id")? as isize,
+        date: parse_time(&row.get::<_, String>("date")?)?,
+        interval,
+        uptime: row.get("uptime")?,
+        heavy: row.get("heavy")?,
+        car: row.get("car")?,
+        bike: row.get("bike")?,
+        pedestrian: row.get("pedestrian")?,
+        heavy_lft: row.get("heavy_lft")?,
+        heavy_rgt: row.get("heavy_rgt")?,
+        car_lft: row.get("car_lft")?,
+        car_rgt: row.get("car_rgt")?,
+        bike_lft: row.get("bike_lft")?,
+        bike_rgt: row.get("bike_rgt")?,
+        pedestrian_lft: row.get("pedestrian_lft")?,
+        pedestrian_rgt: row.get("pedestrian_rgt")?,
+        direction: row.get::<_, i64>("direction")? as usize,
+        timezone: row.get("timezone")?,
+        v85: row.get("v85")?,
+        car_speed_hist_0to70plus: from_json(row, "car_speed_hist_0to70plus")?,
+        car_speed_hist_0to120plus: from_json(row, "car_speed_hist_0to120plus")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{MockResponse, MockServer};
+
+    use super::*;
+
+    fn report_json(date: &str, car: f32) -> String {
+        format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": 0.73, "heavy": 1, "car": {car}, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [50, 50], "car_speed_hist_0to120plus": [], "v85": 25.5}}"#
+        )
+    }
+
+    fn parse_reports(reports: &[String]) -> Vec<Report> {
+        serde_json::from_str(&format!("[{}]", reports.join(", "))).expect("failed to parse json")
+    }
+
+    fn traffic_json(reports: &[String]) -> String {
+        format!(
+            r#"{{"status_code": 200, "message": "ok", "report": [{}]}}"#,
+            reports.join(", ")
+        )
+    }
+
+    fn time(time: &str) -> SystemTime {
+        humantime::parse_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn test_upsert_reports() {
+        let mut store = Store::open_in_memory().expect("failed to open store");
+        let reports = parse_reports(&[
+            report_json("2023-01-01T08:00:00.000Z", 2.0),
+            report_json("2023-01-01T09:00:00.000Z", 3.0),
+        ]);
+
+        assert_eq!(2, store.upsert_reports(&reports).unwrap());
+        // the same reports again, with an updated count, replace the rows
+        let updated = parse_reports(&[report_json("2023-01-01T09:00:00.000Z", 5.0)]);
+        assert_eq!(1, store.upsert_reports(&updated).unwrap());
+
+        let stored = store
+            .reports(
+                348917,
+                time("2023-01-01T00:00:00Z"),
+                time("2023-01-02T00:00:00Z"),
+            )
+            .unwrap();
+        assert_eq!(2, stored.len());
+        assert_eq!(time("2023-01-01T08:00:00Z"), stored[0].date);
+        assert_eq!(5.0, stored[1].car);
+        assert_eq!(vec![50.0, 50.0], stored[1].car_speed_hist_0to70plus);
+    }
+
+    #[test]
+    fn test_sync_traffic() {
+        let server = MockServer::start(vec![
+            MockResponse::ok(traffic_json(&[
+                report_json("2023-01-03T08:00:00.000Z", 2.0),
+                report_json("2023-01-03T09:00:00.000Z", 3.0),
+            ])),
+            // 07:00 was uploaded late, and 09:00 was updated
+            MockResponse::ok(traffic_json(&[
+                report_json("2023-01-03T07:00:00.000Z", 5.0),
+                report_json("2023-01-03T09:00:00.000Z", 4.0),
+                report_json("2023-01-03T10:00:00.000Z", 1.0),
+            ])),
+            MockResponse::ok(traffic_json(&[])),
+        ]);
+        let client = TelraamClient::builder("secret")
+            .base_url(server.url())
+            .build()
+            .expect("failed to build client");
+        let mut store = Store::open_in_memory().expect("failed to open store");
+
+        assert_eq!(None, store.last_synced(348917).unwrap());
+        let since = time("2023-01-03T00:00:00Z");
+        store
+            .sync_traffic(&client, 348917, since, time("2023-01-03T10:00:00Z"))
+            .unwrap();
+        assert_eq!(
+            Some(time("2023-01-03T10:00:00Z")),
+            store.last_synced(348917).unwrap()
+        );
+
+        // the second sync starts the lookback before where the first ended, not at `since`
+        assert_eq!(
+            3,
+            store
+                .sync_traffic(&client, 348917, since, time("2023-01-03T11:00:00Z"))
+                .unwrap()
+        );
+        let stored = store
+            .reports(348917, since, time("2023-01-04T00:00:00Z"))
+            .unwrap();
+        assert_eq!(
+            vec![5.0, 2.0, 4.0, 1.0],
+            stored.iter().map(|report| report.car).collect::<Vec<_>>()
+        );
+
+        // an empty window keeps the rows and still moves the end of the sync
+        assert_eq!(
+            0,
+            store
+                .sync_traffic(&client, 348917, since, time("2023-01-03T12:00:00Z"))
+                .unwrap()
+        );
+        assert_eq!(
+            Some(time("2023-01-03T12:00:00Z")),
+            store.last_synced(348917).unwrap()
+        );
+        assert_eq!(
+            4,
+            store
+                .reports(348917, since, time("2023-01-04T00:00:00Z"))
+                .unwrap()
+                .len()
+        );
+
+        let requests = server.finish();
+        assert_eq!(3, requests.len());
+        assert!(requests[0]
+            .body
+            .contains(r#""time_start":"2023-01-03T00:00:00.000Z""#));
+        assert!(requests[1]
+            .body
+            .contains(r#""time_start":"2023-01-01T10:00:00.000Z""#));
+        assert!(requests[2]
+            .body
+            .contains(r#""time_start":"2023-01-01T11:00:00.000Z""#));
+    }
+
+    #[test]
+    fn test_unknown_interval() {
+        let mut store = Store::open_in_memory().expect("failed to open store");
+        store
+            .upsert_reports(&parse_reports(&[report_json(
+                "2023-01-01T08:00:00.000Z",
+                2.0,
+            )]))
+            .unwrap();
+        store
+            .connection
+            .execute("UPDATE reports SET interval = 'fortnightly'", [])
+            .unwrap();
+
+        let error = store
+            .reports(
+                348917,
+                time("2023-01-01T00:00:00Z"),
+                time("2023-01-02T00:00:00Z"),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Sqlite(rusqlite::Error::FromSqlConversionFailure(..))
+        ));
+    }
+
+    #[test]
+    fn test_truncate_to_hour() {
+        assert_eq!(
+            time("2023-01-01T09:00:00Z"),
+            truncate_to_hour(time("2023-01-01T09:59:59Z"))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! A minimal HTTP server for testing the clients without the real Telraam API
+
+// not all helpers are used with every combination of features
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread::{self, JoinHandle},
+};
+
+/// A request as received by the [`MockServer`]
+#[derive(Debug)]
+pub(crate) struct RecordedRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+
+impl RecordedRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A canned response, each connection to the server gets the next one
+pub(crate) struct MockResponse {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(&'static str, String)>,
+    pub(crate) body: String,
+}
+
+impl MockResponse {
+    pub(crate) fn ok(body: impl Into<String>) -> Self {
+        Self::status(200, body)
+    }
+
+    pub(crate) fn status(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub(crate) fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// Serves the responses in order, one per connection, and then shuts down
+pub(crate) struct MockServer {
+    url: String,
+    handle: JoinHandle<Vec<RecordedRequest>>,
+}
+
+impl MockServer {
+    pub(crate) fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("no local addr"));
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (stream, _) = listener.accept().expect("failed to accept");
+                let mut reader = BufReader::new(stream);
+                requests.push(read_request(&mut reader));
+
+                let mut stream = reader.into_inner();
+                let mut head = format!(
+                    "HTTP/1.1 {} MOCK\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n",
+                    response.status,
+                    response.body.len()
+                );
+                for (name, value) in &response.headers {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+
+                stream
+                    .write_all(head.as_bytes())
+                    .and_then(|()| stream.write_all(response.body.as_bytes()))
+                    .expect("failed to write response");
+            }
+
+            requests
+        });
+
+        Self { url, handle }
+    }
+
+    /// The base URL of the server, e.g. `http://127.0.0.1:12345`
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Waits for all responses to be served, and returns the requests received
+    pub(crate) fn finish(self) -> Vec<RecordedRequest> {
+        self.handle.join().expect("mock server panicked")
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> RecordedRequest {
+    let mut line = String::new();
+    reader.read_line(&mut line).expect("failed to read request");
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("failed to read header");
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).expect("failed to read body");
+
+    RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).expect("body not utf8"),
+    }
+}
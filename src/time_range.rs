@@ -0,0 +1,441 @@
+//! Relative and calendar time ranges, e.g. the last 7 days, since yesterday, a month or an ISO week
+//!
+//! Calendar boundaries (days, months and weeks) are computed in a time zone, usually the local time zone of the
+//! segment, so that "yesterday" is the local day the counts belong to, and not the UTC day. When the time zone of the
+//! segment is not known before fetching, fetch [`TimeRange::resolve_any_timezone`] and narrow the reports down with
+//! [`TimeRange::retain_reports`], which uses the time zone of each report.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+#[cfg(feature = "clap")]
+use clap::Args;
+
+use crate::{error::Error, response::Report};
+
+/// The most local time is ahead of or behind UTC, 14 hours ahead in Kiribati
+const MAX_UTC_OFFSET: Duration = Duration::from_secs(14 * 60 * 60);
+
+/// A point in time, either absolute or relative to now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum When {
+    /// The current time
+    Now,
+    /// The start of the current local day
+    Today,
+    /// The start of the previous local day
+    Yesterday,
+    /// The start of the local day
+    Date(NaiveDate),
+    /// An exact time, e.g. from RFC 3339
+    Time(SystemTime),
+    /// The duration before now
+    Ago(Duration),
+}
+
+impl FromStr for When {
+    type Err = Error;
+
+    /// Parse `now`, `today`, `yesterday`, a date `2024-05-01`, an RFC 3339 time, or a duration ago, e.g. `3d` or `12h ago`
+    fn from_str(when: &str) -> Result<Self, Self::Err> {
+        let when = when.trim();
+
+        match when.to_ascii_lowercase().as_str() {
+            "now" => return Ok(Self::Now),
+            "today" => return Ok(Self::Today),
+            "yesterday" => return Ok(Self::Yesterday),
+            _ => (),
+        }
+
+        if let Ok(date) = NaiveDate::parse_from_str(when, "%Y-%m-%d") {
+            return Ok(Self::Date(date));
+        }
+        if let Ok(time) = humantime::parse_rfc3339_weak(when) {
+            return Ok(Self::Time(time));
+        }
+
+        let ago = when.strip_suffix("ago").unwrap_or(when).trim();
+        humantime::parse_duration(ago)
+            .map(Self::Ago)
+            .map_err(|_| Error::InvalidArgument {
+                name: "time",
+                message: format!(
+                    "expected now, today, yesterday, a date, an RFC 3339 time or a duration ago, got {when}"
+                ),
+            })
+    }
+}
+
+impl When {
+    /// The point in time, with days starting at local midnight in the time zone
+    pub fn resolve<Tz: TimeZone>(&self, now: SystemTime, tz: &Tz) -> SystemTime {
+        let today = DateTime::<Utc>::from(now).with_timezone(tz).date_naive();
+
+        match self {
+            Self::Now => now,
+            Self::Today => start_of_day(today, tz),
+            Self::Yesterday => start_of_day(today - Days::new(1), tz),
+            Self::Date(date) => start_of_day(*date, tz),
+            Self::Time(time) => *time,
+            Self::Ago(ago) => now.checked_sub(*ago).unwrap_or(SystemTime::UNIX_EPOCH),
+        }
+    }
+}
+
+/// A calendar month, parsed from `2024-05`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Month {
+    /// The year
+    pub year: i32,
+    /// The month, from 1 to 12
+    pub month: u32,
+}
+
+impl FromStr for Month {
+    type Err = Error;
+
+    fn from_str(month: &str) -> Result<Self, Self::Err> {
+        NaiveDate::parse_from_str(&format!("{}-01", month.trim()), "%Y-%m-%d")
+            .map(|date| Self {
+                year: date.year(),
+                month: date.month(),
+            })
+            .map_err(|_| Error::InvalidArgument {
+                name: "month",
+                message: format!("expected a month like 2024-05, got {month}"),
+            })
+    }
+}
+
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:02}", self.year, self.month)
+    }
+}
+
+/// An ISO 8601 week, from Monday to Sunday, parsed from `2024-W18`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsoWeek {
+    /// The ISO week based year, which can differ from the calendar year around new year
+    pub year: i32,
+    /// The week, from 1 to 53
+    pub week: u32,
+}
+
+impl FromStr for IsoWeek {
+    type Err = Error;
+
+    fn from_str(week: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArgument {
+            name: "week",
+            message: format!("expected an ISO week like 2024-W18, got {week}"),
+        };
+
+        let (year, number) = week.trim().split_once(['W', 'w']).ok_or_else(invalid)?;
+        let year = year
+            .trim_end_matches('-')
+            .parse::<i32>()
+            .map_err(|_| invalid())?;
+        let number = number.parse::<u32>().map_err(|_| invalid())?;
+
+        NaiveDate::from_isoywd_opt(year, number, Weekday::Mon).ok_or_else(invalid)?;
+        Ok(Self { year, week: number })
+    }
+}
+
+impl fmt::Display for IsoWeek {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-W{:02}", self.year, self.week)
+    }
+}
+
+/// A time range, `[start, end)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeRange {
+    /// Between two points in time
+    Between(When, When),
+    /// The duration up to now
+    Last(Duration),
+    /// From the point in time up to now
+    Since(When),
+    /// A calendar month, in local time
+    Month(Month),
+    /// An ISO week, in local time
+    Week(IsoWeek),
+}
+
+impl TimeRange {
+    /// The start and end of the range in the time zone, relative to `now`
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the range ends before it starts
+    pub fn resolve<Tz: TimeZone>(
+        &self,
+        now: SystemTime,
+        tz: &Tz,
+    ) -> Result<(SystemTime, SystemTime), Error> {
+        let (start, end) =
+            match self {
+                Self::Between(start, end) => (start.resolve(now, tz), end.resolve(now, tz)),
+                Self::Last(duration) => (When::Ago(*duration).resolve(now, tz), now),
+                Self::Since(since) => (since.resolve(now, tz), now),
+                Self::Month(Month { year, month }) => {
+                    let first = NaiveDate::from_ymd_opt(*year, *month, 1).ok_or_else(|| {
+                        Error::InvalidArgument {
+                            name: "month",
+                            message: format!("no such month {year}-{month}"),
+                        }
+                    })?;
+                    (
+                        start_of_day(first, tz),
+                        start_of_day(first + Months::new(1), tz),
+                    )
+                }
+                Self::Week(IsoWeek { year, week }) => {
+                    let monday = NaiveDate::from_isoywd_opt(*year, *week, Weekday::Mon)
+                        .ok_or_else(|| Error::InvalidArgument {
+                            name: "week",
+                            message: format!("no such week {year}-W{week}"),
+                        })?;
+                    (
+                        start_of_day(monday, tz),
+                        start_of_day(monday + Days::new(7), tz),
+                    )
+                }
+            };
+
+        if end < start {
+            return Err(Error::InvalidArgument {
+                name: "time range",
+                message: format!(
+                    "the end {} is before the start {}",
+                    humantime::format_rfc3339_seconds(end),
+                    humantime::format_rfc3339_seconds(start)
+                ),
+            });
+        }
+        Ok((start, end))
+    }
+
+    /// The start and end of the range in any time zone, to fetch the reports before the time zone of their segment is
+    /// known
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the range ends before it starts
+    pub fn resolve_any_timezone(&self, now: SystemTime) -> Result<(SystemTime, SystemTime), Error> {
+        let (start, end) = self.resolve(now, &Utc)?;
+        Ok((start - MAX_UTC_OFFSET, end + MAX_UTC_OFFSET))
+    }
+
+    /// Keep the reports in the range, with the calendar boundaries in the time zone of the segment of each report
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if a report has an unknown time zone, or the range ends before it starts
+    pub fn retain_reports(&self, now: SystemTime, reports: &mut Vec<Report>) -> Result<(), Error> {
+        let mut ranges = HashMap::new();
+        for report in reports.iter() {
+            if !ranges.contains_key(&report.timezone) {
+                ranges.insert(report.timezone.clone(), self.resolve(now, &report.tz()?)?);
+            }
+        }
+
+        reports.retain(|report| {
+            let (start, end) = ranges[&report.timezone];
+            start <= report.date && report.date < end
+        });
+        Ok(())
+    }
+}
+
+/// The time range options of the CLI, exactly one of the ways to give a range must be used
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "clap", derive(Args))]
+#[cfg_attr(feature = "clap", group(skip))]
+#[cfg_attr(
+    feature = "clap",
+    command(group(
+        clap::ArgGroup::new("time_range")
+            .required(true)
+            .args(["time_start", "last", "since", "month", "week"])
+    ))
+)]
+pub struct TimeRangeArgs {
+    /// The beginning of the requested time interval, e.g. `2024-05-01`, `yesterday` or an RFC 3339 time (UTC)
+    #[cfg_attr(feature = "clap", arg(requires = "time_end"))]
+    pub time_start: Option<When>,
+    /// The end of the requested time interval, not included, e.g. `today`, `now` or an RFC 3339 time (UTC)
+    pub time_end: Option<When>,
+    /// The duration up to now, e.g. `7d` or `24h`
+    #[cfg_attr(feature = "clap", arg(long, value_parser = humantime::parse_duration))]
+    pub last: Option<Duration>,
+    /// From the given time up to now, e.g. `yesterday`, `2024-05-01` or `3d`
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub since: Option<When>,
+    /// A calendar month, e.g. `2024-05`
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub month: Option<Month>,
+    /// An ISO week, from Monday to Sunday, e.g. `2024-W18`
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub week: Option<IsoWeek>,
+}
+
+impl TimeRangeArgs {
+    /// The time range from the options, `None` if none was given
+    pub fn range(&self) -> Option<TimeRange> {
+        if let (Some(start), Some(end)) = (self.time_start, self.time_end) {
+            return Some(TimeRange::Between(start, end));
+        }
+
+        self.last
+            .map(TimeRange::Last)
+            .or(self.since.map(TimeRange::Since))
+            .or(self.month.map(TimeRange::Month))
+            .or(self.week.map(TimeRange::Week))
+    }
+}
+
+/// The start of the local day, or the first valid local time after a gap, e.g. if midnight is skipped by DST
+pub(crate) fn start_of_day<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> SystemTime {
+    let mut local = NaiveDateTime::from(date);
+
+    for _ in 0..24 {
+        if let Some(start) = tz.from_local_datetime(&local).earliest() {
+            return start.with_timezone(&Utc).into();
+        }
+        local += chrono::Duration::hours(1);
+    }
+
+    // no valid local time in a whole day, fall back to UTC
+    Utc.from_utc_datetime(&NaiveDateTime::from(date)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::{America::Sao_Paulo, Europe::Brussels};
+
+    use crate::response::Report;
+
+    use super::*;
+
+    fn time(time: &str) -> SystemTime {
+        humantime::parse_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn test_parse_when() {
+        assert_eq!(When::Yesterday, "yesterday".parse::<When>().unwrap());
+        assert_eq!(
+            When::Date(NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()),
+            "2024-05-01".parse::<When>().unwrap()
+        );
+        assert_eq!(
+            When::Time(time("2024-05-01T08:00:00Z")),
+            "2024-05-01T08:00:00Z".parse::<When>().unwrap()
+        );
+        assert_eq!(
+            When::Ago(Duration::from_secs(3 * 24 * 60 * 60)),
+            "3d ago".parse::<When>().unwrap()
+        );
+        assert!("last tuesday".parse::<When>().is_err());
+    }
+
+    #[test]
+    fn test_yesterday_in_local_time() {
+        // just after midnight in Brussels, but still the previous day in UTC
+        let now = time("2024-05-10T22:30:00Z");
+        let (start, end) = TimeRange::Since(When::Yesterday)
+            .resolve(now, &Brussels)
+            .unwrap();
+
+        assert_eq!(time("2024-05-09T22:00:00Z"), start);
+        assert_eq!(now, end);
+    }
+
+    #[test]
+    fn test_month() {
+        let month = "2024-03".parse::<Month>().unwrap();
+        let (start, end) = TimeRange::Month(month)
+            .resolve(SystemTime::now(), &Brussels)
+            .unwrap();
+
+        // the month starts in winter time and ends in summer time
+        assert_eq!(time("2024-02-29T23:00:00Z"), start);
+        assert_eq!(time("2024-03-31T22:00:00Z"), end);
+        assert!("2024-13".parse::<Month>().is_err());
+    }
+
+    fn report(date: &str) -> Report {
+        let json = format!(
+            r#"{{"instance_id": -1, "segment_id": 348917, "date": "{date}", "interval": "hourly", "uptime": 0.73, "heavy": 1, "car": 2, "bike": 3, "pedestrian": 4, "heavy_lft": 0, "heavy_rgt": 1, "car_lft": 1, "car_rgt": 1, "bike_lft": 1, "bike_rgt": 2, "pedestrian_lft": 2, "pedestrian_rgt": 2, "direction": 1, "timezone": "Europe/Brussels", "car_speed_hist_0to70plus": [], "car_speed_hist_0to120plus": [], "v85": 25.5}}"#
+        );
+        serde_json::from_str(&json).expect("failed to parse json")
+    }
+
+    #[test]
+    fn test_retain_reports() {
+        let month = TimeRange::Month("2024-05".parse().unwrap());
+        let now = SystemTime::now();
+        let mut reports = [
+            // 23:00 on the 30th of April in Brussels
+            "2024-04-30T21:00:00Z",
+            "2024-04-30T22:00:00Z",
+            "2024-05-31T21:00:00Z",
+            // midnight on the 1st of June in Brussels
+            "2024-05-31T22:00:00Z",
+        ]
+        .map(report)
+        .to_vec();
+
+        let (start, end) = month.resolve_any_timezone(now).unwrap();
+        assert!(reports
+            .iter()
+            .all(|report| start <= report.date && report.date < end));
+
+        month.retain_reports(now, &mut reports).unwrap();
+        let dates = reports.iter().map(|report| report.date).collect::<Vec<_>>();
+        assert_eq!(
+            vec![time("2024-04-30T22:00:00Z"), time("2024-05-31T21:00:00Z")],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_week() {
+        let week = "2024-W18".parse::<IsoWeek>().unwrap();
+        assert_eq!("2024-W18", week.to_string());
+
+        let (start, end) = TimeRange::Week(week)
+            .resolve(SystemTime::now(), &Brussels)
+            .unwrap();
+        assert_eq!(time("2024-04-28T22:00:00Z"), start);
+        assert_eq!(time("2024-05-05T22:00:00Z"), end);
+
+        // ISO week 1 of 2025 starts in 2024
+        let (start, _) = TimeRange::Week("2025-W01".parse().unwrap())
+            .resolve(SystemTime::now(), &Utc)
+            .unwrap();
+        assert_eq!(time("2024-12-30T00:00:00Z"), start);
+        assert!("2024-W54".parse::<IsoWeek>().is_err());
+    }
+
+    #[test]
+    fn test_midnight_gap() {
+        // Sao Paulo skipped midnight when DST started on 2018-11-04
+        let date = NaiveDate::from_ymd_opt(2018, 11, 4).unwrap();
+        assert_eq!(time("2018-11-04T03:00:00Z"), start_of_day(date, &Sao_Paulo));
+    }
+
+    #[test]
+    fn test_end_before_start() {
+        let range = TimeRange::Between(When::Today, When::Yesterday);
+        assert!(range.resolve(SystemTime::now(), &Utc).is_err());
+    }
+}